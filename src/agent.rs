@@ -1,33 +1,91 @@
 use anyhow::{Context, Result, ensure};
-use rig::agent::Agent;
+use rig::agent::{Agent, AgentBuilder, HookAction, PromptHook};
 use rig::client::CompletionClient;
-use rig::completion::{Message, Prompt, ToolDefinition};
+use rig::completion::{
+    CompletionModel, CompletionResponse, Message, Prompt, ToolDefinition, Usage,
+};
 use rig::providers::openai;
 use rig::tool::Tool;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{Value, json};
+use std::collections::HashSet;
 use std::io::{self, Write};
-use std::sync::Arc;
+use std::sync::atomic::AtomicU64;
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, mpsc, oneshot};
 
+use crate::foreground::JobSignal;
+use crate::redaction::{REDACTION_PROMPT_NOTE, SecretRedactor};
 use crate::session_capture::SessionCapture;
-use crate::terminal_session::TerminalSessionHandle;
+use crate::shell_integration::CommandOutput;
+use crate::terminal_session::{TerminalSessionHandle, TerminalSnapshot};
 
 const DEFAULT_MAX_TURNS: usize = 1_000_000;
 const AGENT_MODEL: &str = "gpt-5.2";
+pub const FINISH_TOOL: &str = "finish";
+const MAX_OUTPUT_LINES: usize = 2000;
+/// A page stops early rather than go past this.
+const MAX_OUTPUT_PAGE_BYTES: usize = 64 * 1024;
 
-type OpenAiAgent = Agent<<openai::Client as CompletionClient>::CompletionModel>;
+type OpenAiModel = <openai::Client as CompletionClient>::CompletionModel;
 
-pub struct AgentRuntime {
-    agent: OpenAiAgent,
+pub struct AgentRuntime<M: CompletionModel = OpenAiModel> {
+    agent: Agent<M>,
     chat_history: Vec<Message>,
     tool_context: Arc<ShellToolContext>,
+    max_turns: usize,
+    usage: Arc<std::sync::Mutex<Usage>>,
 }
 
 pub struct AgentPromptResponse {
     pub output: String,
+    pub input_tokens: u64,
+    pub output_tokens: u64,
     pub total_tokens: u64,
+    /// Set when the model ended the prompt with the `finish` tool.
+    pub finish: Option<TaskResult>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TaskStatus {
+    Success,
+    Failure,
+    Blocked,
+}
+
+impl TaskStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Failure => "failure",
+            Self::Blocked => "blocked",
+        }
+    }
+}
+
+/// The model's own verdict on a task, reported through the `finish` tool.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TaskResult {
+    pub status: TaskStatus,
+    pub summary: String,
+}
+
+/// A tool call waiting on an approver other than this process's own terminal.
+#[derive(Debug)]
+pub struct ApprovalRequest {
+    pub tool_name: String,
+    pub input: String,
+    pub bytes: String,
+    pub reply: oneshot::Sender<bool>,
+}
+
+/// Tool calls made while answering the most recent prompt.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ToolCallStats {
+    pub tool_calls: usize,
+    pub denied: usize,
 }
 
 impl AgentRuntime {
@@ -35,57 +93,244 @@ impl AgentRuntime {
         session: TerminalSessionHandle,
         initial_prompt: &str,
         api_key: &str,
+        base_url: Option<&str>,
         yolo: bool,
         session_capture: Option<SessionCapture>,
+        redactor: Option<SecretRedactor>,
     ) -> Result<Self> {
-        let client: openai::Client =
-            openai::Client::new(api_key).context("failed to create OpenAI client")?;
-        let tool_context = Arc::new(ShellToolContext::new(session, yolo, session_capture));
+        let mut builder = openai::Client::builder().api_key(api_key);
+        if let Some(base_url) = base_url {
+            builder = builder.base_url(base_url);
+        }
+        let client: openai::Client = builder.build().context("failed to create OpenAI client")?;
+        Ok(Self::with_model(
+            client.completion_model(AGENT_MODEL),
+            session,
+            initial_prompt,
+            yolo,
+            session_capture,
+            redactor,
+        ))
+    }
+}
 
-        let agent = client
-            .agent(AGENT_MODEL)
-            .preamble(initial_prompt)
+impl<M: CompletionModel + 'static> AgentRuntime<M> {
+    pub fn with_model(
+        model: M,
+        session: TerminalSessionHandle,
+        initial_prompt: &str,
+        yolo: bool,
+        session_capture: Option<SessionCapture>,
+        redactor: Option<SecretRedactor>,
+    ) -> Self {
+        let preamble = match redactor {
+            Some(_) => format!("{initial_prompt}\n{REDACTION_PROMPT_NOTE}\n"),
+            None => initial_prompt.to_string(),
+        };
+        let tool_context = Arc::new(ShellToolContext::new(
+            session,
+            yolo,
+            session_capture,
+            redactor,
+        ));
+
+        let agent = AgentBuilder::new(model)
+            .preamble(&preamble)
             .default_max_turns(DEFAULT_MAX_TURNS)
             .tool(RawInputTool::new(tool_context.clone()))
+            .tool(CommandOutputTool::new(tool_context.clone()))
+            .tool(SignalTool::new(tool_context.clone()))
+            .tool(ProcessesTool::new(tool_context.clone()))
+            .tool(FinishTool::new(tool_context.clone()))
             .build();
 
-        Ok(Self {
+        Self {
             agent,
             chat_history: Vec::new(),
             tool_context,
-        })
+            max_turns: DEFAULT_MAX_TURNS,
+            usage: Arc::default(),
+        }
+    }
+
+    /// Caps how many model turns a single prompt may take before it fails with
+    /// [`rig::completion::PromptError::MaxTurnsError`].
+    pub fn set_max_turns(&mut self, max_turns: usize) {
+        self.max_turns = max_turns;
     }
 
     pub async fn prompt(&mut self, input: &str) -> Result<AgentPromptResponse> {
-        let response = self
+        self.tool_context.reset_prompt_state();
+        *self
+            .usage
+            .lock()
+            .expect("usage mutex should not be poisoned") = Usage::new();
+        let hook = FinishHook {
+            context: self.tool_context.clone(),
+            usage: self.usage.clone(),
+        };
+        let result = self
             .agent
             .prompt(input)
             .with_history(&mut self.chat_history)
             .with_tool_concurrency(1)
-            .extended_details()
-            .await
-            .map_err(anyhow::Error::from)?;
+            .max_turns(self.max_turns)
+            .with_hook(hook)
+            .await;
+
+        // The hook cancels the loop once `finish` is called, so a recorded result
+        // takes precedence over the error that cancellation produces.
+        let finish = self.tool_context.task_result();
+        let output = match (result, finish.as_ref()) {
+            (_, Some(task)) => task.summary.clone(),
+            (Ok(output), None) => output,
+            (Err(err), None) => return Err(err.into()),
+        };
+        let usage = *self
+            .usage
+            .lock()
+            .expect("usage mutex should not be poisoned");
 
         Ok(AgentPromptResponse {
-            output: response.output,
-            total_tokens: response.total_usage.total_tokens,
+            output,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+            finish,
         })
     }
 
+    pub fn tool_call_stats(&self) -> ToolCallStats {
+        self.tool_context.stats()
+    }
+
+    /// Tokens the last prompt used so far, including one that failed.
+    pub fn last_usage(&self) -> Usage {
+        *self
+            .usage
+            .lock()
+            .expect("usage mutex should not be poisoned")
+    }
+
+    /// Sends confirmations for the model's tool calls to `approver` instead of
+    /// asking on stdin. Has no effect in yolo mode or once an approver is set.
+    pub fn set_approver(&self, approver: mpsc::UnboundedSender<ApprovalRequest>) {
+        self.terminal_input().set_approver(approver);
+    }
+
+    /// A handle for typing into the terminal while a prompt holds the runtime.
+    pub fn terminal_input(&self) -> TerminalInput {
+        TerminalInput {
+            context: self.tool_context.clone(),
+        }
+    }
+
+    /// Current screen as the model would see it, with redaction applied.
+    pub async fn render_screen(&self) -> Result<String> {
+        self.terminal_input().render_screen().await
+    }
+
     pub async fn send_raw_input(&self, spec: &str, wait_seconds: f64) -> Result<String> {
-        // FIXME: handle errors gracefully
-        ensure!(!spec.is_empty(), "usage: :raw <escaped bytes>");
-        let bytes = decode_terminal_input(spec)?;
-        self.tool_context
-            .execute_user_input(bytes, wait_seconds)
+        self.terminal_input()
+            .send_raw_input(spec, wait_seconds)
             .await
     }
 
+    pub async fn send_signal(&self, name: &str, wait_seconds: f64) -> Result<String> {
+        self.terminal_input().send_signal(name, wait_seconds).await
+    }
+
+    /// Tells the model that the user typed into the terminal directly, so it
+    /// does not trust what it last saw. The keystrokes themselves are not shared.
+    pub async fn note_human_takeover(&mut self, duration: Duration) -> Result<()> {
+        let screen = self.render_screen().await?;
+        self.chat_history.push(Message::user(format!(
+            "[The user took over the terminal for {:.0}s and typed into it directly. \
+             The shell state may have changed; this is the screen now:]\n{screen}",
+            duration.as_secs_f64()
+        )));
+        Ok(())
+    }
+
     pub fn reset(&mut self) {
         self.chat_history.clear();
     }
 }
 
+/// User input that takes turns with the model's tool calls on the same terminal.
+#[derive(Clone)]
+pub struct TerminalInput {
+    context: Arc<ShellToolContext>,
+}
+
+impl TerminalInput {
+    /// Terminal access for a driver other than the built-in model, such as an MCP
+    /// client, under the same approval, redaction, and capture rules.
+    pub fn new(
+        session: TerminalSessionHandle,
+        yolo: bool,
+        session_capture: Option<SessionCapture>,
+        redactor: Option<SecretRedactor>,
+    ) -> Self {
+        Self {
+            context: Arc::new(ShellToolContext::new(
+                session,
+                yolo,
+                session_capture,
+                redactor,
+            )),
+        }
+    }
+
+    /// See [`AgentRuntime::set_approver`].
+    pub fn set_approver(&self, approver: mpsc::UnboundedSender<ApprovalRequest>) {
+        let _ = self.context.approver.set(approver);
+    }
+
+    /// Runs the model's `raw_input` tool with JSON `arguments`.
+    pub async fn run_raw_input_tool(&self, arguments: Value) -> Result<String> {
+        let args: ShellInputArgs =
+            serde_json::from_value(arguments).context("invalid raw_input arguments")?;
+        self.context.run_raw_input(&args).await
+    }
+
+    /// Restarts the shell once approved, like a tool call that sends input, and records the call.
+    pub async fn reset_shell(&self, tool_name: &str, params: &Value) -> Result<String> {
+        let screen = self.context.execute_reset(tool_name).await?;
+        self.context
+            .record_tool_call(tool_name, params, &screen.text, Some(&screen.snapshot));
+        Ok(screen.text)
+    }
+
+    /// Renders the screen as the result of a tool call that only looks at it, and records the call.
+    pub async fn screen_tool_call(&self, tool_name: &str, params: &Value) -> Result<String> {
+        let snapshot = self.context.render_snapshot().await?;
+        let screen = self.context.redact_screen(snapshot.render(), &snapshot);
+        self.context
+            .record_tool_call(tool_name, params, &screen, Some(&snapshot));
+        Ok(screen)
+    }
+
+    pub async fn render_screen(&self) -> Result<String> {
+        let snapshot = self.context.render_snapshot().await?;
+        Ok(self.context.redact_screen(snapshot.render(), &snapshot))
+    }
+
+    pub async fn send_raw_input(&self, spec: &str, wait_seconds: f64) -> Result<String> {
+        // FIXME: handle errors gracefully
+        ensure!(!spec.is_empty(), "usage: :raw <escaped bytes>");
+        let bytes = decode_terminal_input(spec)?;
+        self.context.execute_user_input(bytes, wait_seconds).await
+    }
+
+    /// Signals the foreground job without asking, since the user is the one sending it.
+    pub async fn send_signal(&self, name: &str, wait_seconds: f64) -> Result<String> {
+        ensure!(!name.is_empty(), "usage: :signal <INT|TERM|KILL|TSTP|QUIT>");
+        let signal = name.parse()?;
+        self.context.execute_user_signal(signal, wait_seconds).await
+    }
+}
+
 fn decode_terminal_input(spec: &str) -> Result<Vec<u8>> {
     let chars: Vec<char> = spec.chars().collect();
     let mut out = Vec::with_capacity(spec.len());
@@ -136,7 +381,15 @@ struct ShellToolContext {
     session: TerminalSessionHandle,
     yolo: bool,
     session_capture: Option<SessionCapture>,
+    redactor: Option<SecretRedactor>,
     execution_lock: Arc<Mutex<()>>,
+    stats: Arc<std::sync::Mutex<ToolCallStats>>,
+    task_result: Arc<std::sync::Mutex<Option<TaskResult>>>,
+    approver: Arc<OnceLock<mpsc::UnboundedSender<ApprovalRequest>>>,
+    /// Commands whose output is already in the session capture.
+    captured_outputs: Arc<std::sync::Mutex<HashSet<u64>>>,
+    /// The bell count of the last screen this context rendered.
+    seen_bells: Arc<AtomicU64>,
 }
 
 impl ShellToolContext {
@@ -144,18 +397,85 @@ impl ShellToolContext {
         session: TerminalSessionHandle,
         yolo: bool,
         session_capture: Option<SessionCapture>,
+        redactor: Option<SecretRedactor>,
     ) -> Self {
         Self {
             session,
             yolo,
             session_capture,
+            redactor,
             execution_lock: Arc::new(Mutex::new(())),
+            stats: Arc::default(),
+            task_result: Arc::default(),
+            approver: Arc::default(),
+            captured_outputs: Arc::default(),
+            seen_bells: Arc::default(),
+        }
+    }
+
+    fn stats(&self) -> ToolCallStats {
+        *self
+            .stats
+            .lock()
+            .expect("stats mutex should not be poisoned")
+    }
+
+    fn reset_prompt_state(&self) {
+        *self
+            .stats
+            .lock()
+            .expect("stats mutex should not be poisoned") = ToolCallStats::default();
+        self.set_task_result(None);
+    }
+
+    fn task_result(&self) -> Option<TaskResult> {
+        self.task_result
+            .lock()
+            .expect("task result mutex should not be poisoned")
+            .clone()
+    }
+
+    fn set_task_result(&self, result: Option<TaskResult>) {
+        *self
+            .task_result
+            .lock()
+            .expect("task result mutex should not be poisoned") = result;
+    }
+
+    fn count_tool_call(&self, denied: bool) {
+        let mut stats = self
+            .stats
+            .lock()
+            .expect("stats mutex should not be poisoned");
+        stats.tool_calls += 1;
+        if denied {
+            stats.denied += 1;
         }
     }
 
-    fn record_tool_call<T: Serialize>(&self, tool_name: &str, params: &T, snapshot: &str) {
+    /// `terminal` is where the shell was when `result` was rendered; without one the event
+    /// just goes without a directory and title.
+    fn record_tool_call<T: Serialize>(
+        &self,
+        tool_name: &str,
+        params: &T,
+        result: &str,
+        terminal: Option<&TerminalSnapshot>,
+    ) {
         if let Some(session_capture) = self.session_capture.as_ref() {
-            session_capture.record_tool_call(tool_name, params, snapshot);
+            let cwd = terminal
+                .and_then(|terminal| terminal.cwd.clone())
+                .map(|cwd| self.redact(cwd));
+            let title = terminal
+                .and_then(|terminal| terminal.title.clone())
+                .map(|title| self.redact(title));
+            session_capture.record_tool_call(
+                tool_name,
+                params,
+                result,
+                cwd.as_deref(),
+                title.as_deref(),
+            );
         }
     }
 
@@ -168,27 +488,22 @@ impl ShellToolContext {
         let spec = spec.to_string();
         let preview = render_bytes(bytes);
 
-        tokio::task::spawn_blocking(move || -> Result<bool> {
-            eprintln!();
-            eprintln!("approval required for LLM tool call");
-            eprintln!("tool: {tool_name}");
-            eprintln!("input: {spec}");
-            eprintln!("bytes: {preview}");
-            print!("allow sending these bytes to the shell? [y/N]: ");
-            io::stdout()
-                .flush()
-                .context("failed to flush confirmation prompt")?;
-
-            let mut answer = String::new();
-            io::stdin()
-                .read_line(&mut answer)
-                .context("failed to read confirmation response")?;
-
-            let answer = answer.trim().to_ascii_lowercase();
-            Ok(matches!(answer.as_str(), "y" | "yes"))
-        })
-        .await
-        .context("failed to join confirmation prompt task")?
+        if let Some(approver) = self.approver.get() {
+            let (reply, answer) = oneshot::channel();
+            approver
+                .send(ApprovalRequest {
+                    tool_name,
+                    input: spec,
+                    bytes: preview,
+                    reply,
+                })
+                .ok()
+                .context("approver is gone")?;
+            // An approver that drops the request denies it.
+            return Ok(answer.await.unwrap_or(false));
+        }
+
+        confirm_on_stdin(tool_name, spec, preview).await
     }
 
     async fn execute_tool_call(
@@ -197,39 +512,273 @@ impl ShellToolContext {
         spec: &str,
         bytes: Vec<u8>,
         wait_seconds: f64,
-    ) -> Result<String> {
+    ) -> Result<Screen> {
         validate_wait_seconds(wait_seconds)?;
 
         let _lock = self.execution_lock.lock().await;
-        if !self.maybe_confirm(tool_name, spec, &bytes).await? {
-            let snapshot = self.session.snapshot().await?;
-            return Ok(format!(
-                "User denied the `{tool_name}` tool call. No bytes were sent.\n\n{}",
-                snapshot.render()
-            ));
+        let approved = self.maybe_confirm(tool_name, spec, &bytes).await?;
+        self.count_tool_call(!approved);
+        if !approved {
+            let snapshot = self.render_snapshot().await?;
+            return Ok(Screen {
+                text: format!(
+                    "User denied the `{tool_name}` tool call. No bytes were sent.\n\n{}",
+                    self.redact_screen(snapshot.render(), &snapshot)
+                ),
+                snapshot,
+            });
         }
 
         self.execute_locked(bytes, wait_seconds).await
     }
 
-    async fn execute_user_input(&self, bytes: Vec<u8>, wait_seconds: f64) -> Result<String> {
+    async fn run_raw_input(&self, args: &ShellInputArgs) -> Result<String> {
+        // FIXME: handle errors gracefully
+        validate_wait_seconds(args.float)?;
+
+        let bytes = decode_terminal_input(&args.str)?;
+        let spec = format!("{:#?}", args.str);
+
+        let screen = self
+            .execute_tool_call(RawInputTool::NAME, &spec, bytes, args.float)
+            .await?;
+
+        self.record_tool_call(
+            RawInputTool::NAME,
+            args,
+            &screen.text,
+            Some(&screen.snapshot),
+        );
+        Ok(screen.text)
+    }
+
+    async fn run_command_output(&self, args: &CommandOutputArgs) -> Result<String> {
+        self.count_tool_call(false);
+        let page = self.command_output_page(args).await?;
+        let terminal = self.session.snapshot().await.ok();
+        self.record_tool_call(CommandOutputTool::NAME, args, &page, terminal.as_ref());
+        Ok(page)
+    }
+
+    async fn run_processes(&self, args: &ProcessesArgs) -> Result<String> {
+        self.count_tool_call(false);
+        let tree = self.session.processes().await?;
+        let table = self.redact(tree.render());
+        let terminal = self.session.snapshot().await.ok();
+        self.record_tool_call(ProcessesTool::NAME, args, &table, terminal.as_ref());
+        Ok(table)
+    }
+
+    async fn command_output_page(&self, args: &CommandOutputArgs) -> Result<String> {
+        ensure!(args.command >= 1, "command must be at least 1");
+        ensure!(args.start_line >= 1, "start_line must be at least 1");
+        let outputs = self.session.command_output(args.command).await?;
+        ensure!(
+            outputs.integrated,
+            "the shell does not report command boundaries, so no output was collected"
+        );
+        let Some(output) = outputs.output else {
+            anyhow::bail!(
+                "command {} is not available; the output of the last {} finished commands is kept",
+                args.command,
+                outputs.kept
+            );
+        };
+        self.capture_command_output(&output);
+
+        let total = output.lines.len();
+        let status = match output.exit {
+            Some(exit) => format!("exited with status {exit}"),
+            None => "finished".to_string(),
+        };
+        let mut page = format!(
+            "Command {} {status} after {:.1}s and printed {total} lines.",
+            args.command,
+            output.duration.as_secs_f64()
+        );
+        if output.dropped_lines > 0 {
+            page.push_str(&format!(
+                " Its first {} lines were not kept, so line 1 is the oldest one left.",
+                output.dropped_lines
+            ));
+        }
+        let start = args.start_line - 1;
+        if start >= total {
+            page.push_str(&format!(
+                "\nThere is nothing from line {} on.",
+                args.start_line
+            ));
+            return Ok(self.redact(page));
+        }
+
+        let line_count = args.line_count.clamp(1, MAX_OUTPUT_LINES);
+        let mut body = String::new();
+        let mut end = start;
+        for line in output.lines.iter().skip(start).take(line_count) {
+            if end > start && body.len() + line.len() >= MAX_OUTPUT_PAGE_BYTES {
+                break;
+            }
+            body.push_str(line.trim_end());
+            body.push('\n');
+            end += 1;
+        }
+        page.push_str(&format!("\nLines {}-{end}:\n{body}", args.start_line));
+        if end < total {
+            page.push_str(&format!(
+                "[{} more lines; continue with start_line {}.]",
+                total - end,
+                end + 1
+            ));
+        } else {
+            page.push_str("[End of output.]");
+        }
+        Ok(self.redact(page))
+    }
+
+    /// Puts a command's full output in the session capture the first time it is read.
+    fn capture_command_output(&self, output: &CommandOutput) {
+        let Some(session_capture) = self.session_capture.as_ref() else {
+            return;
+        };
+        let first_read = self
+            .captured_outputs
+            .lock()
+            .expect("captured outputs mutex should not be poisoned")
+            .insert(output.id);
+        if first_read {
+            let text = self.redact(output.lines.join("\n"));
+            session_capture.record_command_output(output.exit, output.duration, &text);
+        }
+    }
+
+    async fn execute_reset(&self, tool_name: &str) -> Result<Screen> {
+        let _lock = self.execution_lock.lock().await;
+        let approved = self
+            .maybe_confirm(tool_name, "restart the shell", &[])
+            .await?;
+        self.count_tool_call(!approved);
+        if approved {
+            self.session.reset().await?;
+        }
+
+        let snapshot = self.render_snapshot().await?;
+        let screen = self.redact_screen(snapshot.render(), &snapshot);
+        let text = if approved {
+            screen
+        } else {
+            format!(
+                "User denied the `{tool_name}` tool call. The shell was not restarted.\n\n{screen}"
+            )
+        };
+        Ok(Screen { text, snapshot })
+    }
+
+    async fn run_signal(&self, args: &SignalArgs) -> Result<String> {
+        validate_wait_seconds(args.float)?;
+
+        let _lock = self.execution_lock.lock().await;
+        let approved = self
+            .maybe_confirm(
+                SignalTool::NAME,
+                &format!("send {} to the foreground job", args.signal),
+                &[],
+            )
+            .await?;
+        self.count_tool_call(!approved);
+        let screen = if approved {
+            self.signal_locked(args.signal, args.float).await?
+        } else {
+            let snapshot = self.render_snapshot().await?;
+            Screen {
+                text: format!(
+                    "User denied the `{}` tool call. No signal was sent.\n\n{}",
+                    SignalTool::NAME,
+                    self.redact_screen(snapshot.render(), &snapshot)
+                ),
+                snapshot,
+            }
+        };
+
+        self.record_tool_call(SignalTool::NAME, args, &screen.text, Some(&screen.snapshot));
+        Ok(screen.text)
+    }
+
+    async fn execute_user_signal(&self, signal: JobSignal, wait_seconds: f64) -> Result<String> {
         validate_wait_seconds(wait_seconds)?;
 
         let _lock = self.execution_lock.lock().await;
-        self.execute_locked(bytes, wait_seconds).await
+        let screen = self.signal_locked(signal, wait_seconds).await?;
+        Ok(screen.text)
     }
 
-    async fn execute_locked(&self, bytes: Vec<u8>, wait_seconds: f64) -> Result<String> {
-        debug_assert!(wait_seconds >= 0.0 && wait_seconds.is_finite());
+    async fn signal_locked(&self, signal: JobSignal, wait_seconds: f64) -> Result<Screen> {
+        self.session.signal_foreground(signal).await?;
+        self.screen_after(wait_seconds).await
+    }
 
+    async fn execute_user_input(&self, bytes: Vec<u8>, wait_seconds: f64) -> Result<String> {
+        validate_wait_seconds(wait_seconds)?;
+
+        let _lock = self.execution_lock.lock().await;
+        let screen = self.execute_locked(bytes, wait_seconds).await?;
+        Ok(screen.text)
+    }
+
+    async fn execute_locked(&self, bytes: Vec<u8>, wait_seconds: f64) -> Result<Screen> {
         self.session.send_input(bytes).await?;
+        self.screen_after(wait_seconds).await
+    }
+
+    /// Waits, then renders the screen with any resource limit notices.
+    async fn screen_after(&self, wait_seconds: f64) -> Result<Screen> {
+        debug_assert!(wait_seconds >= 0.0 && wait_seconds.is_finite());
 
         if wait_seconds > 0.0 {
             tokio::time::sleep(Duration::from_secs_f64(wait_seconds)).await;
         }
 
-        Ok(self.session.snapshot().await?.render())
+        let snapshot = self.render_snapshot().await?;
+        let mut rendered = snapshot.render();
+        for notice in self.session.take_limit_notices().await? {
+            rendered.push_str("\nResource limit: ");
+            rendered.push_str(&notice);
+        }
+        Ok(Screen {
+            text: self.redact_screen(rendered, &snapshot),
+            snapshot,
+        })
     }
+
+    /// The screen as this reader should see it, with the bells it has not seen yet.
+    async fn render_snapshot(&self) -> Result<TerminalSnapshot> {
+        let mut snapshot = self.session.snapshot().await?;
+        snapshot.count_new_bells(&self.seen_bells);
+        Ok(snapshot)
+    }
+
+    fn redact(&self, rendered: String) -> String {
+        match self.redactor.as_ref() {
+            Some(redactor) => redactor.redact(&rendered),
+            None => rendered,
+        }
+    }
+
+    /// Like `redact`, for text that starts with `snapshot`'s screen, so secrets wrapped
+    /// across its rows are masked too.
+    fn redact_screen(&self, rendered: String, snapshot: &TerminalSnapshot) -> String {
+        match self.redactor.as_ref() {
+            Some(redactor) => {
+                redactor.redact_screen(&rendered, snapshot.cols, snapshot.lines.len())
+            }
+            None => rendered,
+        }
+    }
+}
+
+/// A tool result and the snapshot it shows.
+struct Screen {
+    text: String,
+    snapshot: TerminalSnapshot,
 }
 
 #[derive(Deserialize, Serialize)]
@@ -238,6 +787,22 @@ struct ShellInputArgs {
     float: f64,
 }
 
+#[derive(Deserialize, Serialize)]
+struct SignalArgs {
+    signal: JobSignal,
+    float: f64,
+}
+
+#[derive(Deserialize, Serialize)]
+struct ProcessesArgs {}
+
+#[derive(Deserialize, Serialize)]
+struct CommandOutputArgs {
+    command: usize,
+    start_line: usize,
+    line_count: usize,
+}
+
 #[derive(Debug)]
 struct ShellToolError {
     message: String,
@@ -282,42 +847,244 @@ impl Tool for RawInputTool {
     type Args = ShellInputArgs;
     type Output = String;
 
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        raw_input_definition()
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        Ok(self.context.run_raw_input(&args).await?)
+    }
+}
+
+/// The `raw_input` tool as offered to the model, and to MCP clients.
+pub fn raw_input_definition() -> ToolDefinition {
+    ToolDefinition {
+        name: RawInputTool::NAME.to_string(),
+        description: "Decode the escaped input string and send the exact bytes to the terminal. Returns a snapshot after waiting float seconds.".to_string(),
+        parameters: json!({
+            "type": "object",
+            "properties": {
+                "str": {
+                    "type": "string",
+                    "description": "Escaped bytes spec (supports \\n, \\r, \\t, \\xNN, \\\\)"
+                },
+                "float": {
+                    "type": "number",
+                    "description": "Seconds to wait before capturing the terminal snapshot"
+                }
+            },
+            "required": ["str", "float"],
+            "additionalProperties": false
+        }),
+    }
+}
+
+#[derive(Clone)]
+struct CommandOutputTool {
+    context: Arc<ShellToolContext>,
+}
+
+impl CommandOutputTool {
+    fn new(context: Arc<ShellToolContext>) -> Self {
+        Self { context }
+    }
+}
+
+impl Tool for CommandOutputTool {
+    const NAME: &'static str = "command_output";
+    type Error = ShellToolError;
+    type Args = CommandOutputArgs;
+    type Output = String;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: format!(
+                "Read everything a finished shell command printed, including what scrolled off the screen, one page of lines at a time. Needs the shell to report command boundaries (the snapshot then has a `Command:` line). Pages hold at most {MAX_OUTPUT_LINES} lines."
+            ),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "command": {
+                        "type": "integer",
+                        "description": "Which finished command: 1 is the most recent, 2 the one before, and so on"
+                    },
+                    "start_line": {
+                        "type": "integer",
+                        "description": "First line to return, counting from 1"
+                    },
+                    "line_count": {
+                        "type": "integer",
+                        "description": "How many lines to return; 200 is a good page"
+                    }
+                },
+                "required": ["command", "start_line", "line_count"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        Ok(self.context.run_command_output(&args).await?)
+    }
+}
+
+#[derive(Clone)]
+struct SignalTool {
+    context: Arc<ShellToolContext>,
+}
+
+impl SignalTool {
+    fn new(context: Arc<ShellToolContext>) -> Self {
+        Self { context }
+    }
+}
+
+impl Tool for SignalTool {
+    const NAME: &'static str = "signal";
+    type Error = ShellToolError;
+    type Args = SignalArgs;
+    type Output = String;
+
     async fn definition(&self, _prompt: String) -> ToolDefinition {
         ToolDefinition {
             name: Self::NAME.to_string(),
-            description: "Decode the escaped input string and send the exact bytes to the terminal. Returns a snapshot after waiting float seconds.".to_string(),
+            description: "Send a signal straight to the job in the foreground of the terminal, even when it has turned off the keys that would normally send one (Ctrl-C, Ctrl-Z, Ctrl-\\). Fails when only the shell is in the foreground. Returns a snapshot after waiting float seconds.".to_string(),
             parameters: json!({
                 "type": "object",
                 "properties": {
-                    "str": {
+                    "signal": {
                         "type": "string",
-                        "description": "Escaped bytes spec (supports \\n, \\r, \\t, \\xNN, \\\\)"
+                        "enum": JobSignal::ALL.map(JobSignal::name),
+                        "description": "INT to interrupt, TERM to ask it to exit, KILL to force it, TSTP to suspend it, QUIT to quit with a core dump"
                     },
                     "float": {
                         "type": "number",
                         "description": "Seconds to wait before capturing the terminal snapshot"
                     }
                 },
-                "required": ["str", "float"],
+                "required": ["signal", "float"],
                 "additionalProperties": false
             }),
         }
     }
 
     async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
-        // FIXME: handle errors gracefully
-        validate_wait_seconds(args.float)?;
+        Ok(self.context.run_signal(&args).await?)
+    }
+}
 
-        let bytes = decode_terminal_input(&args.str)?;
-        let spec = format!("{:#?}", args.str);
+#[derive(Clone)]
+struct ProcessesTool {
+    context: Arc<ShellToolContext>,
+}
 
-        let snapshot = self
-            .context
-            .execute_tool_call(Self::NAME, &spec, bytes, args.float)
-            .await?;
+impl ProcessesTool {
+    fn new(context: Arc<ShellToolContext>) -> Self {
+        Self { context }
+    }
+}
 
-        self.context.record_tool_call(Self::NAME, &args, &snapshot);
-        Ok(snapshot)
+impl Tool for ProcessesTool {
+    const NAME: &'static str = "processes";
+    type Error = ShellToolError;
+    type Args = ProcessesArgs;
+    type Output = String;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: "List the shell and every process under it as a tree, with command lines, state, CPU time, and memory. Reads them from the system without sending anything to the terminal, so it works while a program has frozen the screen.".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {},
+                "required": [],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        Ok(self.context.run_processes(&args).await?)
+    }
+}
+
+#[derive(Clone)]
+struct FinishTool {
+    context: Arc<ShellToolContext>,
+}
+
+impl FinishTool {
+    fn new(context: Arc<ShellToolContext>) -> Self {
+        Self { context }
+    }
+}
+
+impl Tool for FinishTool {
+    const NAME: &'static str = FINISH_TOOL;
+    type Error = ShellToolError;
+    type Args = TaskResult;
+    type Output = String;
+
+    async fn definition(&self, _prompt: String) -> ToolDefinition {
+        ToolDefinition {
+            name: Self::NAME.to_string(),
+            description: "Report that the task is over and end your turn. Call this once you have succeeded, failed, or are blocked on something only the user can provide.".to_string(),
+            parameters: json!({
+                "type": "object",
+                "properties": {
+                    "status": {
+                        "type": "string",
+                        "enum": ["success", "failure", "blocked"],
+                        "description": "Whether the task succeeded, failed, or cannot continue without the user"
+                    },
+                    "summary": {
+                        "type": "string",
+                        "description": "Markdown summary of what was done and why it ended this way"
+                    }
+                },
+                "required": ["status", "summary"],
+                "additionalProperties": false
+            }),
+        }
+    }
+
+    async fn call(&self, args: Self::Args) -> Result<Self::Output, Self::Error> {
+        if let Some(session_capture) = self.context.session_capture.as_ref() {
+            session_capture.record_task_finished(&args);
+        }
+        let status = args.status;
+        self.context.set_task_result(Some(args));
+        Ok(format!("Recorded task status `{}`.", status.as_str()))
+    }
+}
+
+/// Ends the rig loop before the next model call once `finish` has been called.
+/// A cancelled prompt does not report usage, so the hook also keeps the tally.
+#[derive(Clone)]
+struct FinishHook {
+    context: Arc<ShellToolContext>,
+    usage: Arc<std::sync::Mutex<Usage>>,
+}
+
+impl<M: CompletionModel> PromptHook<M> for FinishHook {
+    async fn on_completion_call(&self, _prompt: &Message, _history: &[Message]) -> HookAction {
+        match self.context.task_result() {
+            Some(task) => HookAction::terminate(format!("task finished: {}", task.status.as_str())),
+            None => HookAction::cont(),
+        }
+    }
+
+    async fn on_completion_response(
+        &self,
+        _prompt: &Message,
+        response: &CompletionResponse<M::Response>,
+    ) -> HookAction {
+        *self
+            .usage
+            .lock()
+            .expect("usage mutex should not be poisoned") += response.usage;
+        HookAction::cont()
     }
 }
 
@@ -327,6 +1094,31 @@ fn validate_wait_seconds(wait_seconds: f64) -> Result<()> {
     Ok(())
 }
 
+/// Asks the user on this process's terminal whether a tool call may run.
+pub async fn confirm_on_stdin(tool_name: String, input: String, bytes: String) -> Result<bool> {
+    tokio::task::spawn_blocking(move || -> Result<bool> {
+        eprintln!();
+        eprintln!("approval required for LLM tool call");
+        eprintln!("tool: {tool_name}");
+        eprintln!("input: {input}");
+        eprintln!("bytes: {bytes}");
+        eprint!("allow sending these bytes to the shell? [y/N]: ");
+        io::stderr()
+            .flush()
+            .context("failed to flush confirmation prompt")?;
+
+        let mut answer = String::new();
+        io::stdin()
+            .read_line(&mut answer)
+            .context("failed to read confirmation response")?;
+
+        let answer = answer.trim().to_ascii_lowercase();
+        Ok(matches!(answer.as_str(), "y" | "yes"))
+    })
+    .await
+    .context("failed to join confirmation prompt task")?
+}
+
 fn render_bytes(bytes: &[u8]) -> String {
     use std::fmt::Write as _;
 
@@ -346,3 +1138,198 @@ fn render_bytes(bytes: &[u8]) -> String {
 
     out
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{AgentRuntime, TaskStatus};
+    use crate::session_capture::SessionCapture;
+    use crate::terminal_session::TerminalSession;
+    use crate::test_support::{FakeLlmServer, TEST_API_KEY, shell_session_config, wait_for_prompt};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn runs_tool_calls_against_configured_base_url() {
+        let server = FakeLlmServer::start();
+        server.push_tool_call("raw_input", json!({"str": "echo hi\\r", "float": 0.3}));
+        server.push_text("Printed hi.");
+
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        let mut runtime = AgentRuntime::new(
+            session.handle(),
+            "Use the raw_input tool.",
+            TEST_API_KEY,
+            Some(server.base_url()),
+            true,
+            None,
+            None,
+        )
+        .expect("create runtime");
+
+        let response = runtime.prompt("say hi").await.expect("prompt fake model");
+        session.shutdown().await.expect("shutdown session");
+
+        assert_eq!(response.output, "Printed hi.");
+        assert_eq!(response.total_tokens, 30);
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 2);
+        assert!(requests.iter().all(|req| req.path == "/v1/responses"));
+        assert_eq!(
+            requests[0].headers.get("authorization").map(String::as_str),
+            Some("Bearer test-key")
+        );
+        assert!(
+            requests[0].body["tools"]
+                .as_array()
+                .expect("tools array")
+                .iter()
+                .any(|tool| tool["name"] == "raw_input")
+        );
+
+        let followup = requests[1].body.to_string();
+        assert!(followup.contains("function_call_output"));
+        assert!(followup.contains("$ echo hi"));
+    }
+
+    #[tokio::test]
+    async fn finish_tool_ends_the_prompt_with_its_summary() {
+        let server = FakeLlmServer::start();
+        server.push_tool_call(
+            "finish",
+            json!({"status": "blocked", "summary": "Need the **password**."}),
+        );
+        server.push_text("Still here.");
+
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        let capture = SessionCapture::new();
+        let mut runtime = AgentRuntime::new(
+            session.handle(),
+            "Use the raw_input tool.",
+            TEST_API_KEY,
+            Some(server.base_url()),
+            true,
+            Some(capture.clone()),
+            None,
+        )
+        .expect("create runtime");
+
+        let finished = runtime.prompt("log in").await.expect("finish prompt");
+        let next = runtime.prompt("anything else?").await;
+        session.shutdown().await.expect("shutdown session");
+
+        let task = finished.finish.expect("task result");
+        assert_eq!(task.status, TaskStatus::Blocked);
+        assert_eq!(finished.output, "Need the **password**.");
+        assert_eq!(finished.total_tokens, 15);
+        assert_eq!(server.requests().len(), 2);
+
+        let next = next.expect("prompt after finish");
+        assert_eq!(next.output, "Still here.");
+        assert_eq!(next.finish, None);
+        let followup = server.requests()[1].body.to_string();
+        assert!(followup.contains("function_call_output"));
+        assert!(followup.contains("Recorded task status"));
+        assert!(
+            capture
+                .render_jsonl()
+                .expect("render capture")
+                .contains(r#""type":"task_finished","#)
+        );
+    }
+
+    #[tokio::test]
+    async fn pages_through_the_output_of_finished_commands() {
+        let server = FakeLlmServer::start();
+        server.push_tool_call("raw_input", json!({"str": "seq 300\\r", "float": 0.5}));
+        server.push_tool_call(
+            "command_output",
+            json!({"command": 1, "start_line": 1, "line_count": 100}),
+        );
+        server.push_tool_call(
+            "command_output",
+            json!({"command": 1, "start_line": 251, "line_count": 100}),
+        );
+        server.push_text("Read it all.");
+
+        let mut config = shell_session_config();
+        config.shell.integration = true;
+        let mut session = TerminalSession::start(config).await.expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        let capture = SessionCapture::new();
+        let mut runtime = AgentRuntime::new(
+            session.handle(),
+            "Use the raw_input tool.",
+            TEST_API_KEY,
+            Some(server.base_url()),
+            true,
+            Some(capture.clone()),
+            None,
+        )
+        .expect("create runtime");
+
+        runtime.prompt("count").await.expect("prompt fake model");
+        session.shutdown().await.expect("shutdown session");
+
+        let requests = server.requests();
+        let first_page = requests[2].body.to_string();
+        assert!(
+            first_page.contains("Command 1 exited with status 0"),
+            "{first_page}"
+        );
+        assert!(first_page.contains("printed 300 lines"), "{first_page}");
+        assert!(
+            first_page.contains(r"Lines 1-100:\\n1\\n2\\n"),
+            "{first_page}"
+        );
+        assert!(
+            first_page.contains("continue with start_line 101"),
+            "{first_page}"
+        );
+        let last_page = requests[3].body.to_string();
+        assert!(
+            last_page.contains(r"Lines 251-300:\\n251\\n"),
+            "{last_page}"
+        );
+        assert!(last_page.contains(r"300\\n[End of output.]"), "{last_page}");
+
+        let jsonl = capture.render_jsonl().expect("render capture");
+        assert_eq!(jsonl.matches(r#""type":"command_output""#).count(), 1);
+        assert!(jsonl.contains(r#""exit_status":0"#));
+    }
+
+    #[tokio::test]
+    async fn surfaces_provider_errors() {
+        let server = FakeLlmServer::start();
+        server.push_error(500, "fake upstream failure");
+
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        let mut runtime = AgentRuntime::new(
+            session.handle(),
+            "Use the raw_input tool.",
+            TEST_API_KEY,
+            Some(server.base_url()),
+            true,
+            None,
+            None,
+        )
+        .expect("create runtime");
+
+        let err = runtime
+            .prompt("say hi")
+            .await
+            .err()
+            .expect("provider error");
+        session.shutdown().await.expect("shutdown session");
+
+        assert!(err.to_string().contains("fake upstream failure"), "{err}");
+    }
+}
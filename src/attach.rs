@@ -0,0 +1,193 @@
+use anyhow::{Context, Result, ensure};
+use rustix::event::{PollFd, PollFlags, Timespec, poll};
+use rustix::termios::{OptionalActions, Termios, tcgetattr, tcgetwinsize, tcsetattr};
+use std::io::{self, IsTerminal, Write};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc;
+
+use crate::terminal_session::{TerminalSessionHandle, TerminalSnapshot};
+
+/// Ctrl-], as in telnet.
+pub const DETACH_KEY: u8 = 0x1d;
+pub const DETACH_KEY_NAME: &str = "Ctrl-]";
+
+const STDIN_POLL_INTERVAL: Duration = Duration::from_millis(50);
+const RESIZE_POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// Hands the user's terminal to the session until the detach key is pressed,
+/// and returns how long the user was attached.
+pub async fn attach(session: &TerminalSessionHandle) -> Result<Duration> {
+    ensure!(
+        io::stdin().is_terminal() && io::stdout().is_terminal(),
+        ":attach needs an interactive terminal"
+    );
+
+    // The user's terminal answers queries now, so the session must not answer them too.
+    let (snapshot, output) = session.attach_output().await?;
+    let original_size = (
+        u16::try_from(snapshot.cols).context("terminal columns exceed u16 range")?,
+        u16::try_from(snapshot.rows).context("terminal rows exceed u16 range")?,
+    );
+    let started = Instant::now();
+
+    let raw_mode = RawMode::enable()?;
+    let result = proxy(session, &snapshot, output).await;
+    drop(raw_mode);
+
+    let restored = session.resize(original_size.0, original_size.1).await;
+    result?;
+    restored?;
+    Ok(started.elapsed())
+}
+
+async fn proxy(
+    session: &TerminalSessionHandle,
+    snapshot: &TerminalSnapshot,
+    mut output: mpsc::UnboundedReceiver<Vec<u8>>,
+) -> Result<()> {
+    let mut stdout = io::stdout();
+    stdout
+        .write_all(snapshot.redraw_sequence().as_bytes())
+        .and_then(|()| stdout.flush())
+        .context("failed to draw the session screen")?;
+
+    let mut size = local_size();
+    if let Some((cols, rows)) = size {
+        session.resize(cols, rows).await?;
+    }
+
+    let stop = Arc::new(AtomicBool::new(false));
+    let (input_tx, mut input) = mpsc::unbounded_channel();
+    let reader = {
+        let stop = stop.clone();
+        thread::Builder::new()
+            .name("attach-stdin".to_string())
+            .spawn(move || read_stdin(&stop, &input_tx))
+            .context("failed to spawn stdin reader")?
+    };
+    let mut resize_tick = tokio::time::interval(RESIZE_POLL_INTERVAL);
+
+    let result: Result<()> = async {
+        loop {
+            tokio::select! {
+                bytes = input.recv() => match bytes {
+                    Some(bytes) => session.send_input(bytes).await?,
+                    None => return Ok(()),
+                },
+                bytes = output.recv() => match bytes {
+                    Some(bytes) => stdout
+                        .write_all(&bytes)
+                        .and_then(|()| stdout.flush())
+                        .context("failed to write session output")?,
+                    None => return Ok(()),
+                },
+                _ = resize_tick.tick() => {
+                    let current = local_size();
+                    if current != size && let Some((cols, rows)) = current {
+                        session.resize(cols, rows).await?;
+                    }
+                    size = current;
+                }
+            }
+        }
+    }
+    .await;
+
+    stop.store(true, Ordering::Relaxed);
+    let reader_result = tokio::task::spawn_blocking(move || reader.join())
+        .await
+        .context("failed to join stdin reader")?;
+    result?;
+    match reader_result {
+        Ok(read) => read,
+        Err(_) => anyhow::bail!("stdin reader panicked"),
+    }
+}
+
+/// Forwards stdin until the detach key; polls so a stopped proxy never leaves a
+/// read pending that would swallow the REPL's next keystroke.
+fn read_stdin(stop: &AtomicBool, input: &mpsc::UnboundedSender<Vec<u8>>) -> Result<()> {
+    let stdin = io::stdin();
+    let mut buf = [0_u8; 4096];
+    let timeout = Timespec {
+        tv_sec: 0,
+        tv_nsec: i64::from(STDIN_POLL_INTERVAL.subsec_nanos()),
+    };
+
+    while !stop.load(Ordering::Relaxed) {
+        let mut fds = [PollFd::new(&stdin, PollFlags::IN)];
+        match poll(&mut fds, Some(&timeout)) {
+            Ok(0) => continue,
+            Ok(_) => {}
+            Err(rustix::io::Errno::INTR) => continue,
+            Err(err) => return Err(err).context("failed to poll stdin"),
+        }
+
+        // Read the fd directly: bytes left in std's stdin buffer would be invisible to poll.
+        let n = match rustix::io::read(&stdin, &mut buf) {
+            Ok(0) => return Ok(()),
+            Ok(n) => n,
+            Err(rustix::io::Errno::INTR | rustix::io::Errno::AGAIN) => continue,
+            Err(err) => return Err(err).context("failed to read stdin"),
+        };
+        let (forward, detach) = split_at_detach_key(&buf[..n]);
+        if !forward.is_empty() && input.send(forward.to_vec()).is_err() {
+            return Ok(());
+        }
+        if detach {
+            return Ok(());
+        }
+    }
+    Ok(())
+}
+
+fn split_at_detach_key(bytes: &[u8]) -> (&[u8], bool) {
+    match bytes.iter().position(|byte| *byte == DETACH_KEY) {
+        Some(idx) => (&bytes[..idx], true),
+        None => (bytes, false),
+    }
+}
+
+fn local_size() -> Option<(u16, u16)> {
+    let size = tcgetwinsize(io::stdout()).ok()?;
+    (size.ws_col > 0 && size.ws_row > 0).then_some((size.ws_col, size.ws_row))
+}
+
+/// Puts stdin in raw mode and restores the original settings on drop.
+struct RawMode {
+    original: Termios,
+}
+
+impl RawMode {
+    fn enable() -> Result<Self> {
+        let original = tcgetattr(io::stdin()).context("failed to read terminal settings")?;
+        let mut raw = original.clone();
+        raw.make_raw();
+        tcsetattr(io::stdin(), OptionalActions::Now, &raw)
+            .context("failed to put the terminal in raw mode")?;
+        Ok(Self { original })
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = tcsetattr(io::stdin(), OptionalActions::Now, &self.original);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DETACH_KEY, split_at_detach_key};
+
+    #[test]
+    fn stops_forwarding_at_detach_key() {
+        assert_eq!(split_at_detach_key(b"ls\r"), (&b"ls\r"[..], false));
+        assert_eq!(
+            split_at_detach_key(&[b'a', DETACH_KEY, b'b']),
+            (&b"a"[..], true)
+        );
+    }
+}
@@ -0,0 +1,146 @@
+use clap::ValueEnum;
+use rig::completion::{PromptError, Usage};
+use serde::Serialize;
+use std::process::ExitCode;
+
+use crate::agent::{TaskResult, TaskStatus};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum OutputFormat {
+    /// Render the agent's markdown response for a human.
+    #[default]
+    Text,
+    /// Print one JSON object describing the run.
+    Json,
+}
+
+/// Outcome of a `-c` run, in order of precedence when several apply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BatchStatus {
+    Success,
+    AgentError,
+    BudgetExceeded,
+    ApprovalDenied,
+    TaskFailed,
+    TaskBlocked,
+}
+
+impl BatchStatus {
+    pub fn from_error(err: &anyhow::Error) -> Self {
+        match err.downcast_ref::<PromptError>() {
+            Some(PromptError::MaxTurnsError { .. }) => Self::BudgetExceeded,
+            _ => Self::AgentError,
+        }
+    }
+
+    /// Status of a prompt that completed; the model's own `finish` verdict wins
+    /// over denied tool calls because it usually explains them.
+    pub fn completed(task: Option<TaskStatus>, denied_tool_calls: usize) -> Self {
+        match task {
+            Some(TaskStatus::Failure) => Self::TaskFailed,
+            Some(TaskStatus::Blocked) => Self::TaskBlocked,
+            _ if denied_tool_calls > 0 => Self::ApprovalDenied,
+            _ => Self::Success,
+        }
+    }
+
+    pub fn exit_code(self) -> ExitCode {
+        ExitCode::from(match self {
+            Self::Success => 0,
+            Self::AgentError => 1,
+            Self::BudgetExceeded => 3,
+            Self::ApprovalDenied => 4,
+            Self::TaskFailed => 5,
+            Self::TaskBlocked => 6,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TokenUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+    pub total_tokens: u64,
+}
+
+impl From<Usage> for TokenUsage {
+    fn from(usage: Usage) -> Self {
+        Self {
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+            total_tokens: usage.total_tokens,
+        }
+    }
+}
+
+/// Machine-readable summary printed by `--output json`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchReport {
+    pub status: BatchStatus,
+    pub response: Option<String>,
+    pub error: Option<String>,
+    pub usage: TokenUsage,
+    pub tool_calls: usize,
+    pub denied_tool_calls: usize,
+    pub finish: Option<TaskResult>,
+    pub final_screen: String,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{BatchReport, BatchStatus, TokenUsage};
+    use crate::agent::TaskStatus;
+    use rig::completion::{Message, PromptError};
+    use std::process::ExitCode;
+
+    #[test]
+    fn max_turns_error_is_budget_exceeded() {
+        let err = anyhow::Error::from(PromptError::MaxTurnsError {
+            max_turns: 2,
+            chat_history: Box::default(),
+            prompt: Box::new(Message::user("hi")),
+        });
+
+        assert_eq!(BatchStatus::from_error(&err), BatchStatus::BudgetExceeded);
+        assert_eq!(
+            BatchStatus::from_error(&anyhow::anyhow!("provider down")),
+            BatchStatus::AgentError
+        );
+        assert_eq!(BatchStatus::BudgetExceeded.exit_code(), ExitCode::from(3));
+    }
+
+    #[test]
+    fn serializes_report_with_snake_case_status() {
+        let report = BatchReport {
+            status: BatchStatus::ApprovalDenied,
+            response: Some("done".to_string()),
+            error: None,
+            usage: TokenUsage::default(),
+            tool_calls: 2,
+            denied_tool_calls: 1,
+            finish: None,
+            final_screen: "$ ▮".to_string(),
+        };
+
+        let json = serde_json::to_value(&report).expect("serialize report");
+        assert_eq!(json["status"], "approval_denied");
+        assert_eq!(json["usage"]["total_tokens"], 0);
+        assert_eq!(json["denied_tool_calls"], 1);
+    }
+
+    #[test]
+    fn finish_verdict_outranks_denied_tool_calls() {
+        assert_eq!(BatchStatus::completed(None, 0), BatchStatus::Success);
+        assert_eq!(BatchStatus::completed(None, 1), BatchStatus::ApprovalDenied);
+        assert_eq!(
+            BatchStatus::completed(Some(TaskStatus::Success), 1),
+            BatchStatus::ApprovalDenied
+        );
+        assert_eq!(
+            BatchStatus::completed(Some(TaskStatus::Blocked), 1),
+            BatchStatus::TaskBlocked
+        );
+        assert_eq!(BatchStatus::TaskFailed.exit_code(), ExitCode::from(5));
+    }
+}
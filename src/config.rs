@@ -1,6 +1,7 @@
 use anyhow::{Context, Result, ensure};
 use serde::Deserialize;
 use std::collections::BTreeMap;
+use std::ffi::{OsStr, OsString};
 use std::fs;
 use std::path::{Path, PathBuf};
 
@@ -11,6 +12,92 @@ pub struct ShellConfig {
     pub program: String,
     pub args: Vec<String>,
     pub env: BTreeMap<String, String>,
+    #[serde(default)]
+    pub cwd: Option<PathBuf>,
+    #[serde(default)]
+    pub inherit_env: InheritEnv,
+    #[serde(default)]
+    pub unset_env: Vec<String>,
+    #[serde(default)]
+    pub login: bool,
+    #[serde(default)]
+    pub interactive: bool,
+    #[serde(default = "default_true")]
+    pub integration: bool,
+    #[serde(default = "default_true")]
+    pub report_foreground: bool,
+}
+
+impl ShellConfig {
+    /// Arguments for `program`, dropping the bash flags that would skip the startup files a
+    /// login or interactive shell is expected to read.
+    pub fn effective_args(&self) -> Vec<String> {
+        let mut args: Vec<String> = self
+            .args
+            .iter()
+            .filter(|arg| !(self.login && *arg == "--noprofile"))
+            .filter(|arg| !(self.interactive && *arg == "--norc"))
+            .cloned()
+            .collect();
+        if self.interactive && !args.iter().any(|arg| arg == "-i") {
+            args.push("-i".to_string());
+        }
+        args
+    }
+
+    /// `argv[0]` for a login shell: the program's file name prefixed with `-`, as `login` does.
+    pub fn arg0(&self) -> Option<String> {
+        if !self.login {
+            return None;
+        }
+        let name = Path::new(&self.program).file_name().map_or_else(
+            || self.program.clone(),
+            |name| name.to_string_lossy().into_owned(),
+        );
+        Some(format!("-{name}"))
+    }
+
+    /// The shell's full environment after applying `inherit_env`, `unset_env`, and `env`.
+    pub fn environment(&self) -> BTreeMap<OsString, OsString> {
+        let mut environment: BTreeMap<OsString, OsString> = match &self.inherit_env {
+            InheritEnv::Mode(InheritEnvMode::All) => std::env::vars_os().collect(),
+            InheritEnv::Mode(InheritEnvMode::None) => BTreeMap::new(),
+            InheritEnv::Allowlist(names) => names
+                .iter()
+                .filter_map(|name| Some((OsString::from(name), std::env::var_os(name)?)))
+                .collect(),
+        };
+        for name in &self.unset_env {
+            environment.remove(OsStr::new(name));
+        }
+        // Explicit `shell.env` entries always win over inherited and unset variables.
+        environment.extend(
+            self.env
+                .iter()
+                .map(|(key, value)| (OsString::from(key), OsString::from(value))),
+        );
+        environment
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(untagged)]
+pub enum InheritEnv {
+    Mode(InheritEnvMode),
+    Allowlist(Vec<String>),
+}
+
+impl Default for InheritEnv {
+    fn default() -> Self {
+        Self::Mode(InheritEnvMode::All)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum InheritEnvMode {
+    All,
+    None,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -20,6 +107,18 @@ pub struct SessionConfig {
     pub yolo: bool,
     pub shell: ShellConfig,
     pub llm: LlmConfig,
+    #[serde(default)]
+    pub redaction: RedactionConfig,
+    #[serde(default)]
+    pub sandbox: SandboxConfig,
+    #[serde(default)]
+    pub landlock: LandlockConfig,
+    #[serde(default)]
+    pub limits: LimitsConfig,
+    #[serde(default)]
+    pub serve: ServeConfig,
+    #[serde(default)]
+    pub watch: WatchConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -27,10 +126,131 @@ pub struct LlmConfig {
     #[serde(default)]
     pub api_key: String,
     #[serde(default)]
+    pub base_url: Option<String>,
+    #[serde(default)]
     pub skin: SkinMode,
     pub initial_prompt: String,
 }
 
+#[derive(Debug, Clone, Deserialize)]
+pub struct RedactionConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub env_vars: Vec<String>,
+    #[serde(default = "default_true")]
+    pub detect_secrets: bool,
+}
+
+impl Default for RedactionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            env_vars: Vec::new(),
+            detect_secrets: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct SandboxConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default)]
+    pub workdir: Option<PathBuf>,
+    #[serde(default = "default_sandbox_read_only")]
+    pub read_only: Vec<PathBuf>,
+    #[serde(default = "default_true")]
+    pub network: bool,
+}
+
+impl Default for SandboxConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            workdir: None,
+            read_only: default_sandbox_read_only(),
+            network: true,
+        }
+    }
+}
+
+fn default_sandbox_read_only() -> Vec<PathBuf> {
+    [
+        "/usr", "/bin", "/sbin", "/lib", "/lib32", "/lib64", "/etc", "/opt",
+    ]
+    .into_iter()
+    .map(PathBuf::from)
+    .collect()
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct LandlockConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_landlock_read")]
+    pub read: Vec<PathBuf>,
+    #[serde(default = "default_landlock_write")]
+    pub write: Vec<PathBuf>,
+}
+
+impl Default for LandlockConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            read: default_landlock_read(),
+            write: default_landlock_write(),
+        }
+    }
+}
+
+fn default_landlock_read() -> Vec<PathBuf> {
+    let mut read = default_sandbox_read_only();
+    read.push(PathBuf::from("/proc"));
+    read
+}
+
+fn default_landlock_write() -> Vec<PathBuf> {
+    [".", "/tmp", "/dev"]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect()
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct LimitsConfig {
+    #[serde(default)]
+    pub cpu_seconds: Option<u64>,
+    #[serde(default)]
+    pub address_space_mb: Option<u64>,
+    #[serde(default)]
+    pub open_files: Option<u64>,
+    #[serde(default)]
+    pub processes: Option<u64>,
+    #[serde(default)]
+    pub memory_max_mb: Option<u64>,
+    #[serde(default)]
+    pub cpu_max_percent: Option<u32>,
+    #[serde(default)]
+    pub cgroup_parent: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ServeConfig {
+    #[serde(default)]
+    pub token: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct WatchConfig {
+    #[serde(default)]
+    pub enabled: bool,
+}
+
+fn default_true() -> bool {
+    true
+}
+
 #[derive(Debug, Clone, Copy, Deserialize, PartialEq, Eq, Default)]
 #[serde(rename_all = "lowercase")]
 pub enum SkinMode {
@@ -61,6 +281,41 @@ impl SessionConfig {
         Ok(())
     }
 
+    /// Directory the shell starts in; relative `shell.cwd` values resolve against gibberish's own.
+    pub fn shell_cwd(&self) -> Result<PathBuf> {
+        let current = std::env::current_dir().context("failed to determine current directory")?;
+        let Some(cwd) = self.shell.cwd.as_ref() else {
+            return Ok(current);
+        };
+        let cwd = current.join(cwd);
+        ensure!(
+            cwd.is_dir(),
+            "shell.cwd {} is not a directory",
+            cwd.display()
+        );
+        Ok(cwd)
+    }
+
+    pub fn validate_isolation(&self) -> Result<()> {
+        ensure!(
+            !(self.sandbox.enabled && self.landlock.enabled),
+            "sandbox.enabled and landlock.enabled are mutually exclusive"
+        );
+        Ok(())
+    }
+
+    /// gibberish's own cgroup always holds gibberish, and cgroup v2 refuses to enable
+    /// controllers for the children of a cgroup with processes in it.
+    pub fn validate_limits(&self) -> Result<()> {
+        ensure!(
+            self.limits.cgroup_parent.is_some()
+                || (self.limits.memory_max_mb.is_none() && self.limits.cpu_max_percent.is_none()),
+            "limits.memory_max_mb and limits.cpu_max_percent need limits.cgroup_parent, \
+             a delegated cgroup v2 directory without processes of its own"
+        );
+        Ok(())
+    }
+
     pub fn resolve_llm_api_key(&mut self, env_api_key: Option<String>) {
         if !self.llm.api_key.trim().is_empty() {
             return;
@@ -75,6 +330,20 @@ impl SessionConfig {
 }
 
 pub fn resolve_session_options(cli_path: Option<&Path>) -> Result<SessionConfig> {
+    let (config, path) = load_config_file(cli_path)?;
+    config
+        .validate_llm()
+        .with_context(|| format!("invalid llm settings in config file {}", path.display()))?;
+    Ok(config)
+}
+
+/// Like [`resolve_session_options`], but for running commands in the plain shell, which
+/// must keep working without any LLM settings.
+pub fn resolve_shell_options(cli_path: Option<&Path>) -> Result<SessionConfig> {
+    load_config_file(cli_path).map(|(config, _)| config)
+}
+
+fn load_config_file(cli_path: Option<&Path>) -> Result<(SessionConfig, PathBuf)> {
     let path = match cli_path {
         Some(path) => path.to_path_buf(),
         None => {
@@ -92,10 +361,24 @@ pub fn resolve_session_options(cli_path: Option<&Path>) -> Result<SessionConfig>
     config
         .terminal_size()
         .with_context(|| format!("invalid terminal size in config file {}", path.display()))?;
+    config.validate_isolation().with_context(|| {
+        format!(
+            "invalid isolation settings in config file {}",
+            path.display()
+        )
+    })?;
     config
-        .validate_llm()
-        .with_context(|| format!("invalid llm settings in config file {}", path.display()))?;
-    Ok(config)
+        .validate_limits()
+        .with_context(|| format!("invalid limits in config file {}", path.display()))?;
+    Ok((config, path))
+}
+
+/// Directory for data gibberish keeps between runs, such as REPL history.
+pub fn default_state_dir() -> Result<PathBuf> {
+    let home = std::env::var_os("HOME")
+        .map(PathBuf::from)
+        .context("failed to determine HOME directory for the state directory")?;
+    Ok(home.join(".local").join("state").join("gibberish"))
 }
 
 fn default_config_path() -> Result<PathBuf> {
@@ -132,8 +415,9 @@ fn parse_usize_env_var(env: &BTreeMap<String, String>, key: &str) -> Result<usiz
 #[cfg(test)]
 mod tests {
     use super::{
-        DEFAULT_CONFIG_CONTENTS, LlmConfig, SessionConfig, ShellConfig, SkinMode,
-        ensure_default_config_file,
+        DEFAULT_CONFIG_CONTENTS, InheritEnv, InheritEnvMode, LandlockConfig, LimitsConfig,
+        LlmConfig, RedactionConfig, SandboxConfig, ServeConfig, SessionConfig, ShellConfig,
+        SkinMode, WatchConfig, ensure_default_config_file,
     };
     use std::collections::BTreeMap;
     use std::fs;
@@ -153,12 +437,26 @@ mod tests {
                 program: "/bin/bash".to_string(),
                 args: vec!["--noprofile".to_string()],
                 env,
+                cwd: None,
+                inherit_env: InheritEnv::default(),
+                unset_env: Vec::new(),
+                login: false,
+                interactive: false,
+                integration: true,
+                report_foreground: true,
             },
             llm: LlmConfig {
                 api_key: api_key.to_string(),
+                base_url: None,
                 skin: SkinMode::Default,
                 initial_prompt: TEST_INITIAL_PROMPT.to_string(),
             },
+            redaction: RedactionConfig::default(),
+            sandbox: SandboxConfig::default(),
+            landlock: LandlockConfig::default(),
+            limits: LimitsConfig::default(),
+            serve: ServeConfig::default(),
+            watch: WatchConfig::default(),
         }
     }
 
@@ -209,6 +507,7 @@ initial_prompt = "Use raw_input tool."
 
         assert!(!parsed.yolo);
         assert_eq!(parsed.llm.skin, SkinMode::Default);
+        assert_eq!(parsed.llm.base_url, None);
     }
 
     #[test]
@@ -227,6 +526,7 @@ LINES = "24"
 
 [llm]
 api_key = "config-key"
+base_url = "http://127.0.0.1:8080/v1"
 skin = "dark"
 initial_prompt = "Use raw_input tool."
 "#,
@@ -234,6 +534,10 @@ initial_prompt = "Use raw_input tool."
         .expect("valid session config");
 
         assert_eq!(parsed.llm.skin, SkinMode::Dark);
+        assert_eq!(
+            parsed.llm.base_url.as_deref(),
+            Some("http://127.0.0.1:8080/v1")
+        );
     }
 
     #[test]
@@ -261,6 +565,222 @@ initial_prompt = "Use raw_input tool."
         assert!(parsed.yolo);
     }
 
+    #[test]
+    fn parses_redaction_settings() {
+        let parsed: SessionConfig = toml::from_str(
+            r#"
+wait_ms = 1000
+
+[shell]
+program = "/bin/bash"
+args = ["--noprofile"]
+
+[shell.env]
+COLUMNS = "80"
+LINES = "24"
+
+[llm]
+api_key = "config-key"
+initial_prompt = "Use raw_input tool."
+
+[redaction]
+enabled = true
+env_vars = ["GITHUB_TOKEN"]
+detect_secrets = true
+"#,
+        )
+        .expect("valid session config");
+
+        assert!(parsed.redaction.enabled);
+        assert_eq!(parsed.redaction.env_vars, vec!["GITHUB_TOKEN".to_string()]);
+        assert!(parsed.redaction.detect_secrets);
+    }
+
+    #[test]
+    fn redaction_detects_secrets_by_default() {
+        let parsed: SessionConfig = toml::from_str(
+            r#"
+wait_ms = 1000
+
+[shell]
+program = "/bin/bash"
+args = ["--noprofile"]
+
+[shell.env]
+COLUMNS = "80"
+LINES = "24"
+
+[llm]
+api_key = "config-key"
+initial_prompt = "Use raw_input tool."
+
+[redaction]
+enabled = true
+"#,
+        )
+        .expect("valid session config");
+
+        assert!(parsed.redaction.enabled);
+        assert!(parsed.redaction.env_vars.is_empty());
+        assert!(parsed.redaction.detect_secrets);
+        assert!(RedactionConfig::default().detect_secrets);
+    }
+
+    #[test]
+    fn sandbox_defaults_when_only_enabled() {
+        let parsed: SessionConfig = toml::from_str(
+            r#"
+wait_ms = 1000
+
+[shell]
+program = "/bin/bash"
+args = ["--noprofile"]
+
+[shell.env]
+COLUMNS = "80"
+LINES = "24"
+
+[llm]
+api_key = "config-key"
+initial_prompt = "Use raw_input tool."
+
+[sandbox]
+enabled = true
+network = false
+"#,
+        )
+        .expect("valid session config");
+
+        assert!(parsed.sandbox.enabled);
+        assert!(!parsed.sandbox.network);
+        assert_eq!(parsed.sandbox.workdir, None);
+        assert!(parsed.sandbox.read_only.contains(&PathBuf::from("/usr")));
+        assert!(!base_config("key").sandbox.enabled);
+    }
+
+    #[test]
+    fn parses_resource_limits() {
+        let parsed: SessionConfig = toml::from_str(
+            r#"
+wait_ms = 1000
+
+[shell]
+program = "/bin/bash"
+args = ["--noprofile"]
+
+[shell.env]
+COLUMNS = "80"
+LINES = "24"
+
+[llm]
+api_key = "config-key"
+initial_prompt = "Use raw_input tool."
+
+[limits]
+cpu_seconds = 600
+open_files = 1024
+memory_max_mb = 4096
+"#,
+        )
+        .expect("valid session config");
+
+        assert_eq!(parsed.limits.cpu_seconds, Some(600));
+        assert_eq!(parsed.limits.open_files, Some(1024));
+        assert_eq!(parsed.limits.memory_max_mb, Some(4096));
+        assert_eq!(parsed.limits.processes, None);
+        assert_eq!(parsed.limits.cgroup_parent, None);
+        let err = parsed
+            .validate_limits()
+            .expect_err("memory_max_mb without cgroup_parent");
+        assert!(err.to_string().contains("limits.cgroup_parent"), "{err}");
+        let mut delegated = parsed;
+        delegated.limits.cgroup_parent = Some(PathBuf::from("/sys/fs/cgroup/delegated"));
+        delegated
+            .validate_limits()
+            .expect("limits with a cgroup parent");
+    }
+
+    #[test]
+    fn parses_inherit_env_modes_and_allowlist() {
+        let parse = |inherit_env: &str| -> SessionConfig {
+            toml::from_str(&format!(
+                r#"
+wait_ms = 1000
+
+[shell]
+program = "/bin/bash"
+args = ["--noprofile"]
+cwd = "/tmp"
+inherit_env = {inherit_env}
+unset_env = ["AWS_SECRET_ACCESS_KEY"]
+
+[shell.env]
+COLUMNS = "80"
+LINES = "24"
+
+[llm]
+api_key = "config-key"
+initial_prompt = "Use raw_input tool."
+"#
+            ))
+            .expect("valid session config")
+        };
+
+        assert_eq!(
+            parse(r#""none""#).shell.inherit_env,
+            InheritEnv::Mode(InheritEnvMode::None)
+        );
+        let allowlisted = parse(r#"["PATH", "HOME"]"#);
+        assert_eq!(
+            allowlisted.shell.inherit_env,
+            InheritEnv::Allowlist(vec!["PATH".to_string(), "HOME".to_string()])
+        );
+        assert_eq!(allowlisted.shell.cwd, Some(PathBuf::from("/tmp")));
+        assert_eq!(allowlisted.shell.unset_env, vec!["AWS_SECRET_ACCESS_KEY"]);
+        assert_eq!(
+            base_config("key").shell.inherit_env,
+            InheritEnv::Mode(InheritEnvMode::All)
+        );
+    }
+
+    #[test]
+    fn login_and_interactive_shells_read_startup_files() {
+        let mut shell = base_config("key").shell;
+        shell.args = vec!["--noprofile".to_string(), "--norc".to_string()];
+        assert_eq!(shell.effective_args(), shell.args);
+        assert_eq!(shell.arg0(), None);
+
+        shell.login = true;
+        shell.interactive = true;
+        assert_eq!(shell.effective_args(), vec!["-i".to_string()]);
+        assert_eq!(shell.arg0().as_deref(), Some("-bash"));
+    }
+
+    #[test]
+    fn explicit_env_overrides_unset_and_inherited_values() {
+        let mut shell = base_config("key").shell;
+        shell.inherit_env = InheritEnv::Mode(InheritEnvMode::None);
+        shell.unset_env = vec!["COLUMNS".to_string()];
+
+        let environment = shell.environment();
+
+        assert_eq!(environment.len(), 2);
+        assert_eq!(
+            environment.get(std::ffi::OsStr::new("COLUMNS")),
+            Some(&"80".into())
+        );
+    }
+
+    #[test]
+    fn rejects_sandbox_combined_with_landlock() {
+        let mut config = base_config("key");
+        config.landlock.enabled = true;
+        assert!(config.validate_isolation().is_ok());
+
+        config.sandbox.enabled = true;
+        assert!(config.validate_isolation().is_err());
+    }
+
     #[test]
     fn rejects_missing_initial_prompt() {
         let parsed = toml::from_str::<SessionConfig>(
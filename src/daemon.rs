@@ -0,0 +1,661 @@
+use anyhow::{Context, Result, bail, ensure};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::sync::{Notify, broadcast, mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tracing::{debug, info, warn};
+
+use crate::agent::{AgentRuntime, ApprovalRequest, TaskResult, TerminalInput};
+use crate::config::default_state_dir;
+use crate::repl::ReplOptions;
+use crate::session_capture::{SessionCapture, SessionEvent};
+use crate::terminal_session::{TerminalSession, TerminalSessionHandle, TerminalSnapshot};
+use crate::watch::socket_dir;
+
+pub const DEFAULT_DAEMON_NAME: &str = "default";
+const EVENT_BUFFER: usize = 1024;
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(10);
+const STARTUP_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+/// One JSON object per line from a client. Each request gets exactly one reply,
+/// in order; `prompt` replies once the prompt has started.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DaemonRequest {
+    Prompt {
+        text: String,
+    },
+    RawInput {
+        input: String,
+        #[serde(default)]
+        wait_seconds: Option<f64>,
+    },
+    Snapshot,
+    Reset,
+    Status,
+    /// Streams session events, approval requests, and prompt results to this client.
+    Subscribe,
+    Approve {
+        id: u64,
+        allow: bool,
+    },
+    Shutdown,
+}
+
+/// One JSON object per line to a client: replies interleaved with subscribed events.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum DaemonMessage {
+    Ok,
+    Error {
+        message: String,
+    },
+    Screen {
+        screen: String,
+    },
+    /// The screen as a grid, for clients that draw it themselves.
+    Terminal(TerminalSnapshot),
+    Status {
+        name: String,
+        pid: u32,
+        busy: bool,
+        pending_approvals: usize,
+        subscribers: usize,
+    },
+    Event {
+        event: SessionEvent,
+    },
+    ApprovalRequest {
+        id: u64,
+        tool_name: String,
+        input: String,
+        bytes: String,
+    },
+    PromptFinished {
+        output: Option<String>,
+        error: Option<String>,
+        total_tokens: Option<u64>,
+        finish: Option<TaskResult>,
+    },
+}
+
+/// Socket of the named daemon; kept apart from the `watch` sockets.
+pub fn socket_path(name: &str) -> Result<PathBuf> {
+    ensure!(
+        !name.is_empty() && !name.starts_with('.') && !name.contains('/'),
+        "invalid daemon name `{name}`"
+    );
+    Ok(socket_dir()?.join("daemon").join(format!("{name}.sock")))
+}
+
+/// Re-runs this command as a detached `--foreground` daemon and waits until it listens.
+pub fn spawn_background(name: &str) -> Result<()> {
+    let path = socket_path(name)?;
+    ensure!(
+        std::os::unix::net::UnixStream::connect(&path).is_err(),
+        "daemon `{name}` is already running"
+    );
+    let log_dir = default_state_dir()?;
+    fs::create_dir_all(&log_dir)
+        .with_context(|| format!("failed to create {}", log_dir.display()))?;
+    let log_path = log_dir.join(format!("daemon-{name}.log"));
+    let log = fs::File::create(&log_path)
+        .with_context(|| format!("failed to create daemon log {}", log_path.display()))?;
+
+    let mut command = Command::new(std::env::current_exe().context("failed to locate gibberish")?);
+    command
+        .args(std::env::args_os().skip(1))
+        .arg("--foreground")
+        .stdin(Stdio::null())
+        .stdout(log.try_clone().context("failed to share daemon log")?)
+        .stderr(log);
+    // Leave the caller's session so closing its terminal does not hang up the daemon.
+    unsafe {
+        command.pre_exec(|| {
+            rustix::process::setsid()?;
+            Ok(())
+        })
+    };
+    let mut child = command.spawn().context("failed to start daemon")?;
+
+    let started = Instant::now();
+    while std::os::unix::net::UnixStream::connect(&path).is_err() {
+        if let Some(status) = child.try_wait().context("failed to check daemon")? {
+            bail!("daemon exited with {status}; see {}", log_path.display());
+        }
+        ensure!(
+            started.elapsed() < STARTUP_TIMEOUT,
+            "daemon did not start listening; see {}",
+            log_path.display()
+        );
+        std::thread::sleep(STARTUP_POLL_INTERVAL);
+    }
+    println!(
+        "daemon `{name}` running as pid {}; attach with `gibberish attach {name}`",
+        child.id()
+    );
+    Ok(())
+}
+
+/// Runs the agent for `session` as the daemon called `name` until a client shuts it down.
+pub async fn run_daemon(
+    session: &TerminalSession,
+    name: &str,
+    options: ReplOptions<'_>,
+    session_capture: SessionCapture,
+) -> Result<()> {
+    let runtime = agent_runtime(session, &options, &session_capture)?;
+    serve(
+        &socket_path(name)?,
+        name,
+        session.handle(),
+        runtime,
+        session_capture,
+        Duration::from_millis(options.wait_ms).as_secs_f64(),
+    )
+    .await
+}
+
+/// The agent a [`SessionHost`] drives, recording into `session_capture`.
+pub fn agent_runtime(
+    session: &TerminalSession,
+    options: &ReplOptions<'_>,
+    session_capture: &SessionCapture,
+) -> Result<AgentRuntime> {
+    let mut runtime = AgentRuntime::new(
+        session.handle(),
+        options.initial_prompt,
+        options.api_key,
+        options.base_url,
+        options.yolo,
+        Some(session_capture.clone()),
+        options.redactor.clone(),
+    )?;
+    if let Some(max_turns) = options.max_turns {
+        runtime.set_max_turns(max_turns);
+    }
+    Ok(runtime)
+}
+
+/// Serves the session and agent on `path` until a client asks for shutdown.
+pub async fn serve(
+    path: &Path,
+    name: &str,
+    session: TerminalSessionHandle,
+    runtime: AgentRuntime,
+    capture: SessionCapture,
+    wait_seconds: f64,
+) -> Result<()> {
+    let listener = bind(path)?;
+    let host = SessionHost::start(name, session, runtime, capture, wait_seconds);
+    info!("daemon `{name}` listening on {}", path.display());
+
+    let result = loop {
+        tokio::select! {
+            accepted = listener.accept() => match accepted {
+                Ok((stream, _)) => {
+                    let host = host.clone();
+                    tokio::spawn(async move {
+                        if let Err(err) = serve_client(host, stream).await {
+                            debug!("daemon client disconnected: {err:#}");
+                        }
+                    });
+                }
+                Err(err) => break Err(err).context("daemon socket stopped accepting"),
+            },
+            () = host.shutdown_requested() => break Ok(()),
+        }
+    };
+
+    let _ = fs::remove_file(path);
+    result
+}
+
+fn bind(path: &Path) -> Result<UnixListener> {
+    if let Some(dir) = path.parent() {
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)
+            .with_context(|| {
+                format!("failed to create daemon socket directory {}", dir.display())
+            })?;
+    }
+    if path.exists() {
+        ensure!(
+            std::os::unix::net::UnixStream::connect(path).is_err(),
+            "a daemon is already listening on {}",
+            path.display()
+        );
+        // Left behind by a daemon that was killed.
+        let _ = fs::remove_file(path);
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("failed to bind daemon socket {}", path.display()))?;
+    fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+        .with_context(|| format!("failed to restrict daemon socket {}", path.display()))?;
+    Ok(listener)
+}
+
+async fn serve_client(host: Arc<SessionHost>, stream: UnixStream) -> Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let (client, mut outgoing) = mpsc::unbounded_channel::<DaemonMessage>();
+    let write_loop = tokio::spawn(async move {
+        while let Some(message) = outgoing.recv().await {
+            let mut line =
+                serde_json::to_string(&message).context("failed to serialize daemon message")?;
+            line.push('\n');
+            writer
+                .write_all(line.as_bytes())
+                .await
+                .context("failed to write to daemon client")?;
+        }
+        anyhow::Ok(())
+    });
+
+    let mut subscription: Option<JoinHandle<()>> = None;
+    let mut lines = BufReader::new(reader).lines();
+    let result = async {
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .context("failed to read from daemon client")?
+        {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let reply = match serde_json::from_str::<DaemonRequest>(&line) {
+                Ok(DaemonRequest::Subscribe) => {
+                    if subscription.is_none() {
+                        subscription = Some(forward_events(&host, client.clone()));
+                    }
+                    DaemonMessage::Ok
+                }
+                Ok(request) => host.handle(request).await.unwrap_or_else(error_message),
+                Err(err) => DaemonMessage::Error {
+                    message: format!("invalid request: {err}"),
+                },
+            };
+            if client.send(reply).is_err() {
+                break;
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    if let Some(subscription) = subscription {
+        subscription.abort();
+    }
+    write_loop.abort();
+    result
+}
+
+fn forward_events(
+    host: &SessionHost,
+    client: mpsc::UnboundedSender<DaemonMessage>,
+) -> JoinHandle<()> {
+    let (pending, mut events) = host.subscribe();
+    tokio::spawn(async move {
+        for request in pending {
+            if client.send(request).is_err() {
+                return;
+            }
+        }
+        loop {
+            match events.recv().await {
+                Ok(message) => {
+                    if client.send(message).is_err() {
+                        return;
+                    }
+                }
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("daemon client fell behind and missed {skipped} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => return,
+            }
+        }
+    })
+}
+
+pub fn error_message(err: anyhow::Error) -> DaemonMessage {
+    DaemonMessage::Error {
+        message: format!("{err:#}"),
+    }
+}
+
+/// The session, agent, and pending approvals shared by every client of a daemon
+/// or of the HTTP API.
+pub struct SessionHost {
+    name: String,
+    session: TerminalSessionHandle,
+    terminal: TerminalInput,
+    runtime: Arc<tokio::sync::Mutex<AgentRuntime>>,
+    capture: SessionCapture,
+    events: broadcast::Sender<DaemonMessage>,
+    approvals: Arc<Mutex<PendingApprovals>>,
+    wait_seconds: f64,
+    shutdown: Notify,
+}
+
+#[derive(Default)]
+struct PendingApprovals {
+    next_id: u64,
+    pending: BTreeMap<u64, (DaemonMessage, oneshot::Sender<bool>)>,
+}
+
+impl SessionHost {
+    /// Takes over approvals and capture events from `runtime` and `capture`.
+    pub fn start(
+        name: &str,
+        session: TerminalSessionHandle,
+        runtime: AgentRuntime,
+        capture: SessionCapture,
+        wait_seconds: f64,
+    ) -> Arc<Self> {
+        let (approver, approval_requests) = mpsc::unbounded_channel();
+        runtime.set_approver(approver);
+        let (events, _) = broadcast::channel(EVENT_BUFFER);
+        capture.on_event({
+            let events = events.clone();
+            move |event| {
+                let _ = events.send(DaemonMessage::Event {
+                    event: event.clone(),
+                });
+            }
+        });
+        let approvals = Arc::default();
+        tokio::spawn(forward_approvals(
+            approval_requests,
+            Arc::clone(&approvals),
+            events.clone(),
+        ));
+
+        Arc::new(Self {
+            name: name.to_string(),
+            session,
+            terminal: runtime.terminal_input(),
+            runtime: Arc::new(tokio::sync::Mutex::new(runtime)),
+            capture,
+            events,
+            approvals,
+            wait_seconds,
+            shutdown: Notify::new(),
+        })
+    }
+
+    pub fn session(&self) -> &TerminalSessionHandle {
+        &self.session
+    }
+
+    pub fn capture(&self) -> &SessionCapture {
+        &self.capture
+    }
+
+    /// Resolves once a client has sent `shutdown`.
+    pub async fn shutdown_requested(&self) {
+        self.shutdown.notified().await;
+    }
+
+    /// Answers every request except `subscribe`, which depends on the transport.
+    pub async fn handle(self: &Arc<Self>, request: DaemonRequest) -> Result<DaemonMessage> {
+        Ok(match request {
+            DaemonRequest::Prompt { text } => {
+                self.start_prompt(text)?;
+                DaemonMessage::Ok
+            }
+            DaemonRequest::RawInput {
+                input,
+                wait_seconds,
+            } => DaemonMessage::Screen {
+                screen: self
+                    .terminal
+                    .send_raw_input(&input, wait_seconds.unwrap_or(self.wait_seconds))
+                    .await?,
+            },
+            DaemonRequest::Snapshot => DaemonMessage::Screen {
+                screen: self.session.snapshot().await?.render(),
+            },
+            DaemonRequest::Reset => {
+                let Ok(mut runtime) = self.runtime.try_lock() else {
+                    bail!("a prompt is running; wait for it before resetting");
+                };
+                self.session.reset().await?;
+                runtime.reset();
+                DaemonMessage::Screen {
+                    screen: self.session.snapshot().await?.render(),
+                }
+            }
+            DaemonRequest::Status => DaemonMessage::Status {
+                name: self.name.clone(),
+                pid: std::process::id(),
+                busy: self.runtime.try_lock().is_err(),
+                pending_approvals: self.pending_approvals().len(),
+                subscribers: self.events.receiver_count(),
+            },
+            DaemonRequest::Subscribe => bail!("subscribe needs a streaming connection"),
+            DaemonRequest::Approve { id, allow } => {
+                let (_, reply) = self
+                    .approvals
+                    .lock()
+                    .expect(APPROVALS_LOCK)
+                    .pending
+                    .remove(&id)
+                    .with_context(|| format!("no pending approval with id {id}"))?;
+                // The prompt may have ended while the question was open.
+                let _ = reply.send(allow);
+                DaemonMessage::Ok
+            }
+            DaemonRequest::Shutdown => {
+                self.shutdown.notify_one();
+                DaemonMessage::Ok
+            }
+        })
+    }
+
+    /// Starts `text` in the background; the receiver gets its `prompt_finished` message.
+    pub fn start_prompt(
+        self: &Arc<Self>,
+        text: String,
+    ) -> Result<oneshot::Receiver<DaemonMessage>> {
+        let Ok(mut runtime) = self.runtime.clone().try_lock_owned() else {
+            bail!("a prompt is already running");
+        };
+        let (done, finished) = oneshot::channel();
+        let host = self.clone();
+        tokio::spawn(async move {
+            host.capture.record_user_input(&text);
+            let message = match runtime.prompt(&text).await {
+                Ok(response) => {
+                    host.capture.record_assistant_response(&response.output);
+                    DaemonMessage::PromptFinished {
+                        output: Some(response.output),
+                        error: None,
+                        total_tokens: Some(response.total_tokens),
+                        finish: response.finish,
+                    }
+                }
+                Err(err) => DaemonMessage::PromptFinished {
+                    output: None,
+                    error: Some(format!("{err:#}")),
+                    total_tokens: None,
+                    finish: None,
+                },
+            };
+            drop(runtime);
+            let _ = host.events.send(message.clone());
+            let _ = done.send(message);
+        });
+        Ok(finished)
+    }
+
+    /// Approvals still waiting for an answer.
+    pub fn pending_approvals(&self) -> Vec<DaemonMessage> {
+        live_requests(&self.approvals.lock().expect(APPROVALS_LOCK))
+    }
+
+    /// Approvals still waiting for an answer, and every event from now on.
+    pub fn subscribe(&self) -> (Vec<DaemonMessage>, broadcast::Receiver<DaemonMessage>) {
+        // Holding the approvals lock means no request is both replayed and broadcast.
+        let approvals = self.approvals.lock().expect(APPROVALS_LOCK);
+        (live_requests(&approvals), self.events.subscribe())
+    }
+}
+
+const APPROVALS_LOCK: &str = "approvals mutex should not be poisoned";
+
+async fn forward_approvals(
+    mut requests: mpsc::UnboundedReceiver<ApprovalRequest>,
+    approvals: Arc<Mutex<PendingApprovals>>,
+    events: broadcast::Sender<DaemonMessage>,
+) {
+    while let Some(request) = requests.recv().await {
+        let mut approvals = approvals.lock().expect(APPROVALS_LOCK);
+        approvals.pending.retain(|_, (_, reply)| !reply.is_closed());
+        approvals.next_id += 1;
+        let id = approvals.next_id;
+        let message = DaemonMessage::ApprovalRequest {
+            id,
+            tool_name: request.tool_name,
+            input: request.input,
+            bytes: request.bytes,
+        };
+        approvals
+            .pending
+            .insert(id, (message.clone(), request.reply));
+        // With nobody attached the request waits for the next client.
+        let _ = events.send(message);
+    }
+}
+
+fn live_requests(approvals: &PendingApprovals) -> Vec<DaemonMessage> {
+    approvals
+        .pending
+        .values()
+        .filter(|(_, reply)| !reply.is_closed())
+        .map(|(message, _)| message.clone())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DaemonMessage, DaemonRequest, serve};
+    use crate::agent::AgentRuntime;
+    use crate::session_capture::{SessionCapture, SessionEvent};
+    use crate::terminal_session::TerminalSession;
+    use crate::test_support::{FakeLlmServer, TEST_API_KEY, shell_session_config, wait_for_prompt};
+    use serde_json::json;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader, Lines};
+    use tokio::net::UnixStream;
+    use tokio::net::unix::OwnedReadHalf;
+
+    async fn next(lines: &mut Lines<BufReader<OwnedReadHalf>>) -> DaemonMessage {
+        let line = lines
+            .next_line()
+            .await
+            .expect("read message")
+            .expect("daemon open");
+        serde_json::from_str(&line).expect("parse message")
+    }
+
+    #[tokio::test]
+    async fn clients_approve_tool_calls_and_see_events() {
+        let server = FakeLlmServer::start();
+        server.push_tool_call("raw_input", json!({"str": "echo daemon\\r", "float": 0.3}));
+        server.push_text("Printed daemon.");
+
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        let capture = SessionCapture::new();
+        let runtime = AgentRuntime::new(
+            session.handle(),
+            "Use the raw_input tool.",
+            TEST_API_KEY,
+            Some(server.base_url()),
+            false,
+            Some(capture.clone()),
+            None,
+        )
+        .expect("create runtime");
+        let path =
+            std::env::temp_dir().join(format!("gibberish-daemon-{}.sock", std::process::id()));
+        let daemon = tokio::spawn({
+            let path = path.clone();
+            let handle = session.handle();
+            async move { serve(&path, "test", handle, runtime, capture, 0.2).await }
+        });
+        let stream = loop {
+            if let Ok(stream) = UnixStream::connect(&path).await {
+                break stream;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        };
+        let (reader, mut writer) = stream.into_split();
+        let mut lines = BufReader::new(reader).lines();
+        let mut send = async |request: DaemonRequest| {
+            let mut line = serde_json::to_string(&request).expect("serialize request");
+            line.push('\n');
+            writer
+                .write_all(line.as_bytes())
+                .await
+                .expect("send request");
+        };
+
+        send(DaemonRequest::Subscribe).await;
+        send(DaemonRequest::Prompt {
+            text: "say daemon".into(),
+        })
+        .await;
+        send(DaemonRequest::Prompt {
+            text: "again".into(),
+        })
+        .await;
+
+        let (mut oks, mut errors, mut events) = (0, 0, Vec::new());
+        let finished = loop {
+            match next(&mut lines).await {
+                DaemonMessage::ApprovalRequest { id, bytes, .. } => {
+                    assert_eq!(bytes, "echo daemon\\r");
+                    send(DaemonRequest::Approve { id, allow: true }).await;
+                }
+                DaemonMessage::Event { event } => events.push(event),
+                message @ DaemonMessage::PromptFinished { .. } => break message,
+                DaemonMessage::Ok => oks += 1,
+                DaemonMessage::Error { .. } => errors += 1,
+                other => panic!("unexpected message: {other:?}"),
+            }
+        };
+        send(DaemonRequest::Snapshot).await;
+        let screen = next(&mut lines).await;
+        send(DaemonRequest::Shutdown).await;
+        daemon.await.expect("join daemon").expect("serve");
+        session.shutdown().await.expect("shutdown session");
+
+        let DaemonMessage::PromptFinished { output, error, .. } = finished else {
+            unreachable!()
+        };
+        // Subscribe, the first prompt, and the approval; the second prompt was refused.
+        assert_eq!((oks, errors), (3, 1));
+        assert_eq!(output.as_deref(), Some("Printed daemon."));
+        assert_eq!(error, None);
+        assert!(matches!(events[0], SessionEvent::UserInput { .. }));
+        assert!(matches!(events[1], SessionEvent::ToolCall { .. }));
+        assert!(matches!(events[2], SessionEvent::AssistantResponse { .. }));
+        let DaemonMessage::Screen { screen } = screen else {
+            panic!("expected a screen, got {screen:?}");
+        };
+        assert!(screen.contains("$ echo daemon"), "{screen}");
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,276 @@
+use anyhow::{Context, Result, bail};
+use termimad::MadSkin;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::UnixStream;
+use tokio::net::unix::OwnedWriteHalf;
+use tokio::sync::mpsc;
+
+use crate::agent::confirm_on_stdin;
+use crate::config::{SkinMode, default_state_dir};
+use crate::daemon::{DaemonMessage, DaemonRequest, socket_path};
+use crate::line_editor::ReplEditor;
+use crate::repl::{print_agent_response, repl_prompt, resolve_skin};
+use crate::session_capture::SessionEvent;
+
+/// Commands understood by `gibberish attach`; every other line is a prompt.
+pub const ATTACH_COMMANDS: &[&str] = &[
+    ":raw",
+    ":snap",
+    ":reset",
+    ":status",
+    ":detach",
+    ":shutdown",
+    ":help",
+];
+
+/// Runs a REPL against a daemon. Leaving it, or losing the connection, keeps the
+/// daemon and any running prompt alive.
+pub async fn attach(name: &str, skin_mode: SkinMode) -> Result<()> {
+    let path = socket_path(name)?;
+    let stream = UnixStream::connect(&path).await.with_context(|| {
+        format!("no daemon `{name}` is running; start one with `gibberish daemon`")
+    })?;
+    let mut client = DaemonClient::new(stream, resolve_skin(skin_mode));
+
+    match client.request(&DaemonRequest::Status).await? {
+        DaemonMessage::Status { pid, busy, .. } => eprintln!(
+            "attached to daemon `{name}` (pid {pid}){}; :detach leaves it running",
+            if busy { ", a prompt is running" } else { "" }
+        ),
+        reply => bail!("unexpected reply to status: {reply:?}"),
+    }
+    client.expect_ok(&DaemonRequest::Subscribe).await?;
+
+    let state_dir = default_state_dir().ok();
+    let editor = ReplEditor::new(state_dir, ATTACH_COMMANDS)?;
+    loop {
+        client.show_pending().await?;
+        let (prompt, styled_prompt) = repl_prompt(&client.skin, client.last_total_tokens, None);
+        let Some(line) = editor.read_line(prompt, styled_prompt).await? else {
+            break;
+        };
+        client.show_pending().await?;
+
+        let request = match line.trim_end_matches(&['\r', '\n'][..]) {
+            "" => continue,
+            ":detach" | ":quit" | ":q" => break,
+            ":help" => {
+                eprintln!(
+                    "commands: :raw <spec> (send escaped bytes), :snap (snapshot now), :reset (restart shell + clear agent state), :status, :detach (leave the daemon running), :shutdown (stop the daemon). every other line is sent to the agent"
+                );
+                continue;
+            }
+            ":snap" => DaemonRequest::Snapshot,
+            ":reset" => DaemonRequest::Reset,
+            ":status" => DaemonRequest::Status,
+            ":shutdown" => {
+                client.expect_ok(&DaemonRequest::Shutdown).await?;
+                eprintln!("daemon `{name}` is shutting down");
+                break;
+            }
+            raw if raw == ":raw" || raw.starts_with(":raw ") => DaemonRequest::RawInput {
+                input: raw[":raw".len()..].trim_start().to_string(),
+                wait_seconds: None,
+            },
+            command if command.starts_with(':') => {
+                eprintln!("command error: unknown command `{command}`");
+                continue;
+            }
+            text => DaemonRequest::Prompt {
+                text: text.to_string(),
+            },
+        };
+
+        if let DaemonRequest::Prompt { text } = &request {
+            client.echo = Some(text.clone());
+            client.prompt_running = true;
+        }
+        match client.request(&request).await? {
+            DaemonMessage::Ok => client.wait_for_prompt().await?,
+            DaemonMessage::Screen { screen } => println!("{screen}"),
+            DaemonMessage::Status {
+                busy,
+                pending_approvals,
+                subscribers,
+                ..
+            } => eprintln!(
+                "{}, {pending_approvals} pending approvals, {subscribers} attached clients",
+                if busy { "prompt running" } else { "idle" }
+            ),
+            DaemonMessage::Error { message } => {
+                client.prompt_running = false;
+                eprintln!("command error: {message}");
+            }
+            reply => eprintln!("unexpected reply: {reply:?}"),
+        }
+    }
+    Ok(())
+}
+
+struct DaemonClient {
+    writer: OwnedWriteHalf,
+    messages: mpsc::UnboundedReceiver<DaemonMessage>,
+    skin: MadSkin,
+    last_total_tokens: Option<u64>,
+    /// Replies still owed for approvals answered between requests.
+    unacked_approvals: usize,
+    /// Our own prompt, so its `user_input` event is not printed back.
+    echo: Option<String>,
+    prompt_running: bool,
+}
+
+impl DaemonClient {
+    fn new(stream: UnixStream, skin: MadSkin) -> Self {
+        let (reader, writer) = stream.into_split();
+        let (tx, messages) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let mut lines = BufReader::new(reader).lines();
+            while let Ok(Some(line)) = lines.next_line().await {
+                match serde_json::from_str(&line) {
+                    Ok(message) => {
+                        if tx.send(message).is_err() {
+                            return;
+                        }
+                    }
+                    Err(err) => tracing::warn!("ignoring malformed daemon message: {err}"),
+                }
+            }
+        });
+        Self {
+            writer,
+            messages,
+            skin,
+            last_total_tokens: None,
+            unacked_approvals: 0,
+            echo: None,
+            prompt_running: false,
+        }
+    }
+
+    async fn send(&mut self, request: &DaemonRequest) -> Result<()> {
+        let mut line = serde_json::to_string(request).context("failed to serialize request")?;
+        line.push('\n');
+        self.writer
+            .write_all(line.as_bytes())
+            .await
+            .context("failed to send request to daemon")
+    }
+
+    async fn next_message(&mut self) -> Result<DaemonMessage> {
+        self.messages
+            .recv()
+            .await
+            .context("daemon closed the connection")
+    }
+
+    /// Sends `request` and shows events until its reply arrives.
+    async fn request(&mut self, request: &DaemonRequest) -> Result<DaemonMessage> {
+        self.send(request).await?;
+        loop {
+            let message = self.next_message().await?;
+            if self.take_approval_ack(&message) {
+                continue;
+            }
+            match message {
+                DaemonMessage::Event { .. }
+                | DaemonMessage::ApprovalRequest { .. }
+                | DaemonMessage::PromptFinished { .. } => self.show(message).await?,
+                reply => return Ok(reply),
+            }
+        }
+    }
+
+    /// Consumes the reply to an earlier `approve`, if `message` is one.
+    fn take_approval_ack(&mut self, message: &DaemonMessage) -> bool {
+        if self.unacked_approvals == 0
+            || !matches!(message, DaemonMessage::Ok | DaemonMessage::Error { .. })
+        {
+            return false;
+        }
+        self.unacked_approvals -= 1;
+        if let DaemonMessage::Error { message } = message {
+            eprintln!("approval error: {message}");
+        }
+        true
+    }
+
+    async fn expect_ok(&mut self, request: &DaemonRequest) -> Result<()> {
+        match self.request(request).await? {
+            DaemonMessage::Ok => Ok(()),
+            DaemonMessage::Error { message } => bail!("{message}"),
+            reply => bail!("unexpected reply: {reply:?}"),
+        }
+    }
+
+    async fn wait_for_prompt(&mut self) -> Result<()> {
+        while self.prompt_running {
+            let message = self.next_message().await?;
+            if !self.take_approval_ack(&message) {
+                self.show(message).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Shows events that arrived while the user was typing.
+    async fn show_pending(&mut self) -> Result<()> {
+        while let Ok(message) = self.messages.try_recv() {
+            self.show(message).await?;
+        }
+        Ok(())
+    }
+
+    async fn show(&mut self, message: DaemonMessage) -> Result<()> {
+        match message {
+            DaemonMessage::Event { event } => self.show_event(event),
+            DaemonMessage::ApprovalRequest {
+                id,
+                tool_name,
+                input,
+                bytes,
+            } => {
+                let allow = confirm_on_stdin(tool_name, input, bytes).await?;
+                self.send(&DaemonRequest::Approve { id, allow }).await?;
+                self.unacked_approvals += 1;
+            }
+            DaemonMessage::PromptFinished {
+                error,
+                total_tokens,
+                ..
+            } => {
+                self.prompt_running = false;
+                self.echo = None;
+                if let Some(total_tokens) = total_tokens {
+                    self.last_total_tokens = Some(total_tokens);
+                }
+                if let Some(error) = error {
+                    eprintln!("agent error: {error}");
+                }
+            }
+            DaemonMessage::Ok => {}
+            DaemonMessage::Error { message } => eprintln!("daemon error: {message}"),
+            reply => eprintln!("unexpected message: {reply:?}"),
+        }
+        Ok(())
+    }
+
+    fn show_event(&mut self, event: SessionEvent) {
+        match event {
+            SessionEvent::UserInput { text, .. } => {
+                if self.echo.as_deref() != Some(text.as_str()) {
+                    eprintln!("prompt from another client: {text}");
+                }
+            }
+            SessionEvent::ToolCall {
+                tool_name, params, ..
+            } => eprintln!("tool call: {tool_name} {params}"),
+            SessionEvent::AssistantResponse { markdown, .. } => {
+                print_agent_response(&self.skin, &markdown);
+            }
+            SessionEvent::TaskFinished { status, .. } => {
+                eprintln!("task finished: {}", status.as_str());
+            }
+            SessionEvent::CommandOutput { .. } => {}
+        }
+    }
+}
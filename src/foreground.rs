@@ -0,0 +1,282 @@
+use anyhow::{Context, Result, bail, ensure};
+use rustix::process::{Pid, Signal};
+use rustix::termios::tcgetpgrp;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::os::fd::AsFd;
+use std::str::FromStr;
+
+/// Kernel functions a process sleeps in while it reads from the terminal.
+const TTY_READ_WCHANS: &[&str] = &["n_tty_read", "wait_woken"];
+/// Where line editors and full-screen programs sleep while they wait for keys; a program
+/// waiting on a socket looks the same.
+const POLL_WCHANS: &[&str] = &[
+    "poll_schedule_timeout",
+    "do_select",
+    "core_sys_select",
+    "do_sys_poll",
+    "ep_poll",
+    "do_epoll_wait",
+];
+
+/// What the foreground process is doing, as far as `/proc` can tell.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ForegroundActivity {
+    /// Blocked in a `read` on the terminal.
+    ReadingInput,
+    /// Blocked in `poll`, `select`, or `epoll`.
+    Polling,
+    Running,
+    /// Blocked on anything else, such as a timer, a child, or the disk.
+    Sleeping,
+    Stopped,
+}
+
+/// The process in charge of the terminal.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ForegroundProcess {
+    /// As seen from inside the shell's PID namespace.
+    pub pid: i32,
+    pub name: String,
+    pub activity: ForegroundActivity,
+    /// The kernel function it sleeps in, when `/proc` shows one.
+    pub wchan: Option<String>,
+}
+
+impl ForegroundProcess {
+    /// Looks up the foreground process group of the terminal behind `pty`.
+    pub fn detect(pty: impl AsFd) -> Option<Self> {
+        let group = tcgetpgrp(pty).ok()?.as_raw_nonzero().get();
+        Self::read(group_member(group)?)
+    }
+
+    fn read(pid: i32) -> Option<Self> {
+        let stat = ProcStat::read(pid)?;
+        let wchan = fs::read_to_string(format!("/proc/{pid}/wchan"))
+            .ok()
+            .map(|wchan| base_symbol(wchan.trim()).to_string())
+            .filter(|wchan| !wchan.is_empty() && wchan != "0");
+        let activity = match (stat.state, wchan.as_deref()) {
+            ('R', _) => ForegroundActivity::Running,
+            ('T' | 't', _) => ForegroundActivity::Stopped,
+            ('S', Some(wchan)) if TTY_READ_WCHANS.contains(&wchan) => {
+                ForegroundActivity::ReadingInput
+            }
+            ('S', Some(wchan)) if POLL_WCHANS.contains(&wchan) => ForegroundActivity::Polling,
+            _ => ForegroundActivity::Sleeping,
+        };
+        Some(Self {
+            pid: namespace_pid(pid).unwrap_or(pid),
+            name: stat.name,
+            activity,
+            wchan,
+        })
+    }
+
+    /// One footer line for the snapshot.
+    pub fn describe(&self) -> String {
+        let activity = match self.activity {
+            ForegroundActivity::ReadingInput => "waiting for input".to_string(),
+            ForegroundActivity::Polling => {
+                "idle in poll/select, usually waiting for input".to_string()
+            }
+            ForegroundActivity::Running => "busy".to_string(),
+            ForegroundActivity::Sleeping => match &self.wchan {
+                Some(wchan) => format!("sleeping in {wchan}"),
+                None => "sleeping".to_string(),
+            },
+            ForegroundActivity::Stopped => "stopped".to_string(),
+        };
+        format!("Foreground: {} (pid {}), {activity}", self.name, self.pid)
+    }
+}
+
+/// A signal that can be sent to the job in the foreground.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum JobSignal {
+    Int,
+    Term,
+    Kill,
+    Tstp,
+    Quit,
+}
+
+impl JobSignal {
+    pub const ALL: [Self; 5] = [Self::Int, Self::Term, Self::Kill, Self::Tstp, Self::Quit];
+
+    /// Without the `SIG` prefix, as the tool and `:signal` take it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Int => "INT",
+            Self::Term => "TERM",
+            Self::Kill => "KILL",
+            Self::Tstp => "TSTP",
+            Self::Quit => "QUIT",
+        }
+    }
+
+    pub fn signal(self) -> Signal {
+        match self {
+            Self::Int => Signal::INT,
+            Self::Term => Signal::TERM,
+            Self::Kill => Signal::KILL,
+            Self::Tstp => Signal::TSTP,
+            Self::Quit => Signal::QUIT,
+        }
+    }
+}
+
+impl fmt::Display for JobSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "SIG{}", self.name())
+    }
+}
+
+/// Takes `INT`, `SIGINT`, or `int`.
+impl FromStr for JobSignal {
+    type Err = anyhow::Error;
+
+    fn from_str(name: &str) -> Result<Self> {
+        let name = name.trim().to_ascii_uppercase();
+        let name = name.strip_prefix("SIG").unwrap_or(&name);
+        match Self::ALL.into_iter().find(|signal| signal.name() == name) {
+            Some(signal) => Ok(signal),
+            None => bail!("unknown signal {name}; use INT, TERM, KILL, TSTP, or QUIT"),
+        }
+    }
+}
+
+/// The foreground process group of the terminal behind `pty`, unless it is the shell's
+/// own. `spawned` is the process gibberish started: the shell itself or, when
+/// `sandboxed`, the init whose child is the shell.
+pub fn foreground_job(pty: impl AsFd, spawned: i32, sandboxed: bool) -> Result<Pid> {
+    let group = tcgetpgrp(pty).context("failed to find the terminal's foreground process group")?;
+    let leader = group.as_raw_nonzero().get();
+    let is_shell = leader == spawned
+        || (sandboxed && ProcStat::read(leader).is_some_and(|stat| stat.parent == spawned));
+    ensure!(
+        !is_shell,
+        "nothing is running in the foreground; the shell is waiting at its prompt"
+    );
+    Ok(group)
+}
+
+pub struct ProcStat {
+    pub name: String,
+    pub state: char,
+    pub parent: i32,
+    pub group: i32,
+    /// User plus system time, in clock ticks.
+    pub cpu_ticks: Option<u64>,
+    pub rss_pages: Option<u64>,
+}
+
+impl ProcStat {
+    pub fn read(pid: i32) -> Option<Self> {
+        Self::parse(&fs::read_to_string(format!("/proc/{pid}/stat")).ok()?)
+    }
+
+    /// The name is in parentheses and may itself contain them, so fields are found from
+    /// the last `)`.
+    fn parse(stat: &str) -> Option<Self> {
+        let open = stat.find('(')?;
+        let close = stat.rfind(')')?;
+        let fields: Vec<&str> = stat.get(close + 1..)?.split_whitespace().collect();
+        let number = |index: usize| fields.get(index)?.parse::<u64>().ok();
+        Some(Self {
+            name: stat.get(open + 1..close)?.to_string(),
+            state: fields.first()?.chars().next()?,
+            parent: fields.get(1)?.parse().ok()?,
+            group: fields.get(2)?.parse().ok()?,
+            cpu_ticks: number(11)
+                .zip(number(12))
+                .map(|(user, system)| user + system),
+            rss_pages: number(21),
+        })
+    }
+}
+
+/// The group leader, or the newest member once the leader has exited (the end of a
+/// pipeline is usually what is on screen).
+fn group_member(group: i32) -> Option<i32> {
+    let in_group = |pid: i32| ProcStat::read(pid).is_some_and(|stat| stat.group == group);
+    if in_group(group) {
+        return Some(group);
+    }
+    fs::read_dir("/proc")
+        .ok()?
+        .filter_map(|entry| entry.ok()?.file_name().to_str()?.parse::<i32>().ok())
+        .filter(|&pid| in_group(pid))
+        .max()
+}
+
+/// The innermost PID from the `NSpid` line, so sandboxed shells see their own numbering.
+pub fn namespace_pid(pid: i32) -> Option<i32> {
+    fs::read_to_string(format!("/proc/{pid}/status"))
+        .ok()?
+        .lines()
+        .find_map(|line| line.strip_prefix("NSpid:"))?
+        .split_whitespace()
+        .last()?
+        .parse()
+        .ok()
+}
+
+/// Drops compiler suffixes like `.constprop.0` from a kernel symbol.
+fn base_symbol(symbol: &str) -> &str {
+    symbol.split('.').next().unwrap_or(symbol)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ForegroundActivity, ForegroundProcess, JobSignal, ProcStat, base_symbol};
+
+    #[test]
+    fn parses_stat_names_with_spaces_and_parentheses() {
+        let stat = ProcStat::parse("4242 (my (odd) prog) S 1 4240 4240 34816 4240 4194304")
+            .expect("parse stat");
+
+        assert_eq!(stat.name, "my (odd) prog");
+        assert_eq!(stat.state, 'S');
+        assert_eq!(stat.parent, 1);
+        assert_eq!(stat.cpu_ticks, None);
+        assert_eq!(stat.group, 4240);
+        assert_eq!(
+            base_symbol("poll_schedule_timeout.constprop.0"),
+            "poll_schedule_timeout"
+        );
+    }
+
+    #[test]
+    fn describes_the_foreground_process() {
+        let mut process = ForegroundProcess {
+            pid: 12,
+            name: "vim".to_string(),
+            activity: ForegroundActivity::ReadingInput,
+            wchan: Some("wait_woken".to_string()),
+        };
+        assert_eq!(
+            process.describe(),
+            "Foreground: vim (pid 12), waiting for input"
+        );
+
+        process.activity = ForegroundActivity::Sleeping;
+        process.wchan = Some("hrtimer_nanosleep".to_string());
+        assert_eq!(
+            process.describe(),
+            "Foreground: vim (pid 12), sleeping in hrtimer_nanosleep"
+        );
+    }
+
+    #[test]
+    fn parses_signal_names() {
+        for name in ["INT", "SIGINT", "sigint", " int "] {
+            assert_eq!(name.parse::<JobSignal>().expect("parse"), JobSignal::Int);
+        }
+        assert_eq!(JobSignal::Tstp.to_string(), "SIGTSTP");
+        assert!("HUP".parse::<JobSignal>().is_err());
+    }
+}
@@ -0,0 +1,396 @@
+use anyhow::{Context, Result};
+use axum::extract::ws::{Message, WebSocket, WebSocketUpgrade};
+use axum::extract::{Path, Query, Request, State};
+use axum::http::{StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{Html, IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Deserialize;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::broadcast;
+use tokio::time::Instant;
+use tracing::{debug, info, warn};
+
+use crate::daemon::{DaemonMessage, DaemonRequest, SessionHost, agent_runtime, error_message};
+use crate::repl::ReplOptions;
+use crate::session_capture::SessionCapture;
+use crate::terminal_session::TerminalSession;
+
+/// Screen updates on the event stream are sent at most this often.
+const SCREEN_DEBOUNCE: Duration = Duration::from_millis(100);
+const WEB_UI: &str = include_str!("web_ui.html");
+
+/// Serves the session and agent over HTTP on `listen` until a client asks for shutdown.
+pub async fn run_serve(
+    session: &TerminalSession,
+    listen: SocketAddr,
+    token: String,
+    options: ReplOptions<'_>,
+    session_capture: SessionCapture,
+) -> Result<()> {
+    let runtime = agent_runtime(session, &options, &session_capture)?;
+    if !listen.ip().is_loopback() {
+        warn!(
+            "{listen} is reachable from other machines; anyone with the token can use your shell"
+        );
+    }
+    let listener = TcpListener::bind(listen)
+        .await
+        .with_context(|| format!("failed to listen on {listen}"))?;
+    let host = SessionHost::start(
+        &listen.to_string(),
+        session.handle(),
+        runtime,
+        session_capture,
+        Duration::from_millis(options.wait_ms).as_secs_f64(),
+    );
+    info!("serving the HTTP API on http://{listen}");
+    serve(listener, token, host).await
+}
+
+pub async fn serve(listener: TcpListener, token: String, host: Arc<SessionHost>) -> Result<()> {
+    let app = Router::new()
+        .route("/api/prompt", post(prompt))
+        .route("/api/raw", post(raw_input))
+        .route("/api/snapshot", get(snapshot))
+        .route("/api/reset", post(reset))
+        .route("/api/status", get(status))
+        .route("/api/approvals", get(approvals))
+        .route("/api/approvals/{id}", post(approve))
+        .route("/api/shutdown", post(shutdown))
+        .route("/api/capture.jsonl", get(capture_jsonl))
+        .route("/api/capture.html", get(capture_html))
+        .route("/api/events", get(events))
+        .layer(middleware::from_fn_with_state(
+            Arc::new(token),
+            require_token,
+        ))
+        // The page holds no secrets; it asks for the token and sends it with each request.
+        .route("/", get(index))
+        .with_state(host.clone());
+    axum::serve(listener, app)
+        .with_graceful_shutdown(async move { host.shutdown_requested().await })
+        .await
+        .context("HTTP server failed")
+}
+
+#[derive(Deserialize)]
+struct TokenQuery {
+    token: String,
+}
+
+/// Accepts `Authorization: Bearer TOKEN`, or `?token=TOKEN` for browser WebSockets.
+async fn require_token(State(token): State<Arc<String>>, request: Request, next: Next) -> Response {
+    let bearer = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(str::to_string);
+    let given = bearer.or_else(|| {
+        Query::<TokenQuery>::try_from_uri(request.uri())
+            .ok()
+            .map(|query| query.0.token)
+    });
+    match given {
+        Some(given) if tokens_match(&given, &token) => next.run(request).await,
+        _ => StatusCode::UNAUTHORIZED.into_response(),
+    }
+}
+
+/// Compares without stopping at the first difference, so timing does not leak the token.
+fn tokens_match(given: &str, expected: &str) -> bool {
+    given.len() == expected.len()
+        && given
+            .bytes()
+            .zip(expected.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+struct ApiError(anyhow::Error);
+
+impl<E: Into<anyhow::Error>> From<E> for ApiError {
+    fn from(err: E) -> Self {
+        Self(err.into())
+    }
+}
+
+impl IntoResponse for ApiError {
+    fn into_response(self) -> Response {
+        (StatusCode::BAD_REQUEST, Json(error_message(self.0))).into_response()
+    }
+}
+
+type ApiResult = Result<Json<DaemonMessage>, ApiError>;
+
+#[derive(Deserialize)]
+struct PromptBody {
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct RawInputBody {
+    input: String,
+    #[serde(default)]
+    wait_seconds: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct ApproveBody {
+    allow: bool,
+}
+
+/// Waits for the prompt to finish; it keeps running if the client goes away.
+async fn prompt(State(host): State<Arc<SessionHost>>, Json(body): Json<PromptBody>) -> ApiResult {
+    let finished = host.start_prompt(body.text)?;
+    Ok(Json(
+        finished.await.context("prompt ended without a result")?,
+    ))
+}
+
+async fn raw_input(
+    State(host): State<Arc<SessionHost>>,
+    Json(body): Json<RawInputBody>,
+) -> ApiResult {
+    let request = DaemonRequest::RawInput {
+        input: body.input,
+        wait_seconds: body.wait_seconds,
+    };
+    Ok(Json(host.handle(request).await?))
+}
+
+async fn snapshot(State(host): State<Arc<SessionHost>>) -> ApiResult {
+    Ok(Json(host.handle(DaemonRequest::Snapshot).await?))
+}
+
+async fn reset(State(host): State<Arc<SessionHost>>) -> ApiResult {
+    Ok(Json(host.handle(DaemonRequest::Reset).await?))
+}
+
+async fn status(State(host): State<Arc<SessionHost>>) -> ApiResult {
+    Ok(Json(host.handle(DaemonRequest::Status).await?))
+}
+
+async fn approvals(State(host): State<Arc<SessionHost>>) -> Json<Vec<DaemonMessage>> {
+    Json(host.pending_approvals())
+}
+
+async fn approve(
+    State(host): State<Arc<SessionHost>>,
+    Path(id): Path<u64>,
+    Json(body): Json<ApproveBody>,
+) -> ApiResult {
+    let request = DaemonRequest::Approve {
+        id,
+        allow: body.allow,
+    };
+    Ok(Json(host.handle(request).await?))
+}
+
+async fn shutdown(State(host): State<Arc<SessionHost>>) -> ApiResult {
+    Ok(Json(host.handle(DaemonRequest::Shutdown).await?))
+}
+
+async fn index() -> Html<&'static str> {
+    Html(WEB_UI)
+}
+
+async fn capture_jsonl(State(host): State<Arc<SessionHost>>) -> Result<Response, ApiError> {
+    let jsonl = host.capture().render_jsonl()?;
+    Ok(([(header::CONTENT_TYPE, "application/x-ndjson")], jsonl).into_response())
+}
+
+async fn capture_html(State(host): State<Arc<SessionHost>>) -> Html<String> {
+    Html(host.capture().render_html())
+}
+
+async fn events(State(host): State<Arc<SessionHost>>, upgrade: WebSocketUpgrade) -> Response {
+    upgrade.on_upgrade(move |socket| async move {
+        if let Err(err) = stream_events(&host, socket).await {
+            debug!("event stream closed: {err:#}");
+        }
+    })
+}
+
+/// Sends the screen and pending approvals, then every daemon event plus a `terminal`
+/// message whenever the screen changes.
+async fn stream_events(host: &SessionHost, mut socket: WebSocket) -> Result<()> {
+    let (pending, mut events) = host.subscribe();
+    let (snapshot, mut output) = host.session().subscribe_output().await?;
+    send(&mut socket, &DaemonMessage::Terminal(snapshot)).await?;
+    for message in &pending {
+        send(&mut socket, message).await?;
+    }
+
+    let mut redraw_at: Option<Instant> = None;
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(message) => send(&mut socket, &message).await?,
+                Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                    warn!("event stream fell behind and missed {skipped} events");
+                }
+                Err(broadcast::error::RecvError::Closed) => return Ok(()),
+            },
+            bytes = output.recv() => match bytes {
+                Some(_) => {
+                    redraw_at.get_or_insert_with(|| Instant::now() + SCREEN_DEBOUNCE);
+                }
+                None => return Ok(()),
+            },
+            () = tokio::time::sleep_until(redraw_at.unwrap_or_else(Instant::now)),
+                if redraw_at.is_some() =>
+            {
+                redraw_at = None;
+                let snapshot = host.session().snapshot().await?;
+                send(&mut socket, &DaemonMessage::Terminal(snapshot)).await?;
+            }
+            incoming = socket.recv() => match incoming {
+                None | Some(Err(_)) | Some(Ok(Message::Close(_))) => return Ok(()),
+                Some(Ok(_)) => {}
+            },
+        }
+    }
+}
+
+async fn send(socket: &mut WebSocket, message: &DaemonMessage) -> Result<()> {
+    let text = serde_json::to_string(message).context("failed to serialize event")?;
+    socket
+        .send(Message::Text(text.into()))
+        .await
+        .context("failed to send event")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::serve;
+    use crate::agent::AgentRuntime;
+    use crate::daemon::SessionHost;
+    use crate::session_capture::SessionCapture;
+    use crate::terminal_session::TerminalSession;
+    use crate::test_support::{FakeLlmServer, TEST_API_KEY, shell_session_config, wait_for_prompt};
+    use serde_json::{Value, json};
+    use std::net::SocketAddr;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    async fn request(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        token: Option<&str>,
+        body: Option<Value>,
+    ) -> (u16, String) {
+        let body = body.map(|body| body.to_string()).unwrap_or_default();
+        let mut head = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\nContent-Type: application/json\r\nContent-Length: {}\r\n",
+            body.len()
+        );
+        if let Some(token) = token {
+            head.push_str(&format!("Authorization: Bearer {token}\r\n"));
+        }
+        let mut stream = TcpStream::connect(addr).await.expect("connect");
+        stream
+            .write_all(format!("{head}\r\n{body}").as_bytes())
+            .await
+            .expect("send request");
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .expect("read response");
+        let (head, body) = response.split_once("\r\n\r\n").expect("response head");
+        let status = head[9..12].parse().expect("status code");
+        (status, body.to_string())
+    }
+
+    #[tokio::test]
+    async fn serves_prompts_raw_input_and_captures_behind_the_token() {
+        let server = FakeLlmServer::start();
+        server.push_tool_call("raw_input", json!({"str": "echo http\\r", "float": 0.3}));
+        server.push_text("Printed http.");
+
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        let capture = SessionCapture::new();
+        let runtime = AgentRuntime::new(
+            session.handle(),
+            "Use the raw_input tool.",
+            TEST_API_KEY,
+            Some(server.base_url()),
+            true,
+            Some(capture.clone()),
+            None,
+        )
+        .expect("create runtime");
+        let host = SessionHost::start("test", session.handle(), runtime, capture, 0.2);
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let addr = listener.local_addr().expect("local addr");
+        let api = tokio::spawn(serve(listener, "secret".into(), host));
+
+        let (code, _) = request(addr, "GET", "/api/snapshot", None, None).await;
+        assert_eq!(code, 401);
+        let (code, page) = request(addr, "GET", "/", None, None).await;
+        assert_eq!(code, 200);
+        assert!(page.contains("/api/events"));
+        let (code, _) = request(addr, "GET", "/api/snapshot", Some("wrong!"), None).await;
+        assert_eq!(code, 401);
+
+        let token = Some("secret");
+        let (code, body) = request(
+            addr,
+            "POST",
+            "/api/prompt",
+            token,
+            Some(json!({"text": "say http"})),
+        )
+        .await;
+        assert_eq!(code, 200, "{body}");
+        let finished: Value = serde_json::from_str(&body).expect("parse prompt result");
+        assert_eq!(finished["type"], "prompt_finished");
+        assert_eq!(finished["output"], "Printed http.");
+
+        let (code, body) = request(
+            addr,
+            "POST",
+            "/api/raw",
+            token,
+            Some(json!({"input": "echo raw\\r"})),
+        )
+        .await;
+        assert_eq!(code, 200, "{body}");
+        let screen: Value = serde_json::from_str(&body).expect("parse screen");
+        let screen = screen["screen"].as_str().expect("screen text");
+        assert!(screen.contains("$ echo http"), "{screen}");
+        assert!(screen.contains("$ echo raw"), "{screen}");
+
+        let (code, body) =
+            request(addr, "GET", "/api/capture.jsonl?token=secret", None, None).await;
+        assert_eq!(code, 200);
+        assert_eq!(body.lines().count(), 3, "{body}");
+        assert!(body.contains("\"tool_call\""), "{body}");
+
+        let (code, body) = request(
+            addr,
+            "POST",
+            "/api/approvals/7",
+            token,
+            Some(json!({"allow": true})),
+        )
+        .await;
+        assert_eq!(code, 400);
+        assert!(body.contains("no pending approval with id 7"), "{body}");
+
+        let (code, _) = request(addr, "POST", "/api/shutdown", token, None).await;
+        assert_eq!(code, 200);
+        api.await.expect("join server").expect("serve");
+        session.shutdown().await.expect("shutdown session");
+    }
+}
@@ -0,0 +1,199 @@
+use anyhow::{Context, Result};
+use landlock::{
+    ABI, Access, AccessFs, CompatLevel, Compatible, Ruleset, RulesetAttr, RulesetCreatedAttr,
+    path_beneath_rules,
+};
+use std::io;
+use std::os::fd::{AsRawFd, OwnedFd};
+use std::path::{Path, PathBuf};
+
+use crate::config::LandlockConfig;
+
+const TARGET_ABI: ABI = ABI::V5;
+
+/// Resolved Landlock allowlists for the shell and everything it spawns.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LandlockPolicy {
+    pub read: Vec<PathBuf>,
+    pub write: Vec<PathBuf>,
+}
+
+impl LandlockPolicy {
+    /// Relative paths in `config` resolve against the shell's starting directory.
+    pub fn from_config(config: &LandlockConfig, shell_cwd: &Path) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let write = resolve_paths(shell_cwd, &config.write);
+        let read = resolve_paths(shell_cwd, &config.read)
+            .into_iter()
+            .filter(|path| !write.contains(path))
+            .collect();
+        Ok(Some(Self { read, write }))
+    }
+
+    pub fn prompt_note(&self) -> String {
+        format!(
+            "Filesystem access in this terminal is restricted by Landlock. \
+             You may read and execute files under: {}. \
+             You may additionally create, modify, and delete files under: {}. \
+             Anything else fails with \"Permission denied\"; work within these paths instead of retrying.",
+            display_paths(&self.read),
+            display_paths(&self.write)
+        )
+    }
+
+    /// Builds the ruleset in the parent so the forked child only has to call
+    /// `landlock_restrict_self`, which is safe between fork and exec.
+    /// Fails here, rather than in the child, when the kernel has no Landlock.
+    pub fn prepare(&self) -> Result<PreparedLandlock> {
+        let ruleset = Ruleset::default()
+            .set_compatibility(CompatLevel::BestEffort)
+            .handle_access(AccessFs::from_all(TARGET_ABI))
+            .context("failed to configure landlock ruleset")?
+            .create()
+            .context("failed to create landlock ruleset")?
+            .add_rules(path_beneath_rules(
+                &self.read,
+                AccessFs::from_read(TARGET_ABI),
+            ))
+            .context("failed to add landlock read rules")?
+            .add_rules(path_beneath_rules(
+                &self.write,
+                AccessFs::from_all(TARGET_ABI),
+            ))
+            .context("failed to add landlock write rules")?;
+        // Best effort leaves no ruleset fd when the kernel cannot enforce anything.
+        let ruleset: Option<OwnedFd> = ruleset.into();
+        let ruleset = ruleset.context("landlock is not supported or not enabled by this kernel")?;
+        Ok(PreparedLandlock { ruleset })
+    }
+}
+
+pub struct PreparedLandlock {
+    ruleset: OwnedFd,
+}
+
+impl PreparedLandlock {
+    /// Applies the ruleset to the calling process; meant to run in `pre_exec`, so it
+    /// only makes syscalls and neither allocates nor locks.
+    pub fn restrict_self(&self) -> io::Result<()> {
+        rustix::thread::set_no_new_privs(true)?;
+        // SAFETY: the syscall takes a ruleset fd we own and a flags word; no memory is passed.
+        let ret = unsafe {
+            libc::syscall(
+                libc::SYS_landlock_restrict_self,
+                self.ruleset.as_raw_fd(),
+                0,
+            )
+        };
+        if ret != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+fn resolve_paths(cwd: &Path, paths: &[PathBuf]) -> Vec<PathBuf> {
+    let mut resolved: Vec<PathBuf> = Vec::new();
+    // Missing paths are skipped so one default list works across distros.
+    for path in paths
+        .iter()
+        .filter_map(|path| cwd.join(path).canonicalize().ok())
+    {
+        if !resolved.contains(&path) {
+            resolved.push(path);
+        }
+    }
+    resolved
+}
+
+fn display_paths(paths: &[PathBuf]) -> String {
+    if paths.is_empty() {
+        return "(nothing)".to_string();
+    }
+    paths
+        .iter()
+        .map(|path| path.display().to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LandlockPolicy;
+    use crate::config::LandlockConfig;
+    use crate::terminal_session::TerminalSession;
+    use crate::test_support::{shell_session_config, wait_for_prompt};
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    #[test]
+    fn disabled_policy_is_none() {
+        let policy = LandlockPolicy::from_config(&LandlockConfig::default(), Path::new("/"))
+            .expect("policy");
+
+        assert_eq!(policy, None);
+    }
+
+    #[test]
+    fn resolves_relative_paths_and_skips_missing_ones() {
+        let config = LandlockConfig {
+            enabled: true,
+            read: vec![PathBuf::from("/usr"), PathBuf::from("/gibberish-missing")],
+            write: vec![PathBuf::from(".")],
+        };
+
+        let policy = LandlockPolicy::from_config(&config, Path::new("/usr/bin"))
+            .expect("policy")
+            .expect("enabled");
+        assert_eq!(policy.write, vec![PathBuf::from("/usr/bin")]);
+        assert_eq!(policy.read, vec![PathBuf::from("/usr")]);
+        assert!(policy.prompt_note().contains("/usr"));
+    }
+
+    #[tokio::test]
+    async fn denied_writes_show_up_as_permission_errors() {
+        let scratch =
+            std::env::temp_dir().join(format!("gibberish-landlock-{}", std::process::id()));
+        std::fs::create_dir_all(&scratch).expect("create scratch dir");
+        let denied =
+            PathBuf::from("/var/tmp").join(format!("gibberish-landlock-{}", std::process::id()));
+        let mut options = shell_session_config();
+        options.landlock.enabled = true;
+        options.landlock.write = vec![scratch.clone(), PathBuf::from("/dev")];
+
+        let mut session = match TerminalSession::start(options).await {
+            Ok(session) => session,
+            Err(err) if format!("{err:#}").contains("not supported") => {
+                eprintln!("skipping: landlock is unavailable here");
+                return;
+            }
+            Err(err) => panic!("start session: {err:#}"),
+        };
+        wait_for_prompt(&session.handle()).await;
+        let command = format!(
+            "echo ok > {}/allowed; echo no > {}\r",
+            scratch.display(),
+            denied.display()
+        );
+        session
+            .handle()
+            .send_input(command)
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let snapshot = session.snapshot().await.expect("snapshot");
+        session.shutdown().await.expect("shutdown session");
+
+        assert!(
+            snapshot.render().contains("Permission denied"),
+            "{}",
+            snapshot.render()
+        );
+        assert!(scratch.join("allowed").exists());
+        assert!(!denied.exists());
+        std::fs::remove_dir_all(&scratch).expect("remove scratch dir");
+    }
+}
@@ -0,0 +1,312 @@
+use anyhow::{Context, Result};
+use rustyline::completion::{Completer, Pair};
+use rustyline::error::ReadlineError;
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::history::FileHistory;
+use rustyline::validate::{ValidationContext, ValidationResult, Validator};
+use rustyline::{Cmd, CompletionType, Config, Editor, Helper, KeyCode, KeyEvent, Modifiers};
+use std::borrow::Cow;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use tracing::warn;
+
+use crate::foreground::JobSignal;
+
+const HISTORY_FILE: &str = "history";
+const RAW_COMMAND: &str = ":raw";
+const SIGNAL_COMMAND: &str = ":signal";
+const MAX_HISTORY: usize = 10_000;
+
+/// Named keys offered by completion inside a `:raw` payload.
+const RAW_ESCAPES: &[(&str, &str)] = &[
+    ("enter", "\\r"),
+    ("newline", "\\n"),
+    ("tab", "\\t"),
+    ("backspace", "\\x7f"),
+    ("esc", "\\x1b"),
+    ("up", "\\x1b[A"),
+    ("down", "\\x1b[B"),
+    ("right", "\\x1b[C"),
+    ("left", "\\x1b[D"),
+    ("home", "\\x1b[H"),
+    ("end", "\\x1b[F"),
+    ("ctrl-a", "\\x01"),
+    ("ctrl-c", "\\x03"),
+    ("ctrl-d", "\\x04"),
+    ("ctrl-e", "\\x05"),
+    ("ctrl-l", "\\x0c"),
+    ("ctrl-r", "\\x12"),
+    ("ctrl-u", "\\x15"),
+    ("ctrl-w", "\\x17"),
+    ("ctrl-z", "\\x1a"),
+];
+
+/// Line editor for the REPL with completion, multi-line input, and history
+/// that persists across sessions when a history file is available.
+pub struct ReplEditor {
+    editor: Arc<Mutex<Editor<ReplHelper, FileHistory>>>,
+    history_path: Option<PathBuf>,
+}
+
+impl ReplEditor {
+    /// `commands` are the `:` commands offered by completion.
+    pub fn new(state_dir: Option<PathBuf>, commands: &'static [&'static str]) -> Result<Self> {
+        let config = Config::builder()
+            .max_history_size(MAX_HISTORY)
+            .context("invalid history size")?
+            .history_ignore_dups(true)
+            .context("invalid history settings")?
+            .history_ignore_space(true)
+            .completion_type(CompletionType::List)
+            .build();
+        let mut editor = Editor::with_config(config).context("failed to create line editor")?;
+        editor.set_helper(Some(ReplHelper {
+            styled_prompt: String::new(),
+            commands,
+        }));
+        editor.bind_sequence(KeyEvent(KeyCode::Enter, Modifiers::ALT), Cmd::Newline);
+
+        let history_path = state_dir.and_then(|dir| match fs::create_dir_all(&dir) {
+            Ok(()) => Some(dir.join(HISTORY_FILE)),
+            Err(err) => {
+                warn!(
+                    "not saving REPL history: failed to create {}: {err}",
+                    dir.display()
+                );
+                None
+            }
+        });
+        if let Some(path) = history_path.as_ref()
+            && path.exists()
+            && let Err(err) = editor.load_history(path)
+        {
+            warn!("failed to load REPL history from {}: {err}", path.display());
+        }
+
+        Ok(Self {
+            editor: Arc::new(Mutex::new(editor)),
+            history_path,
+        })
+    }
+
+    /// Reads one (possibly multi-line) entry. `prompt` is what the editor measures,
+    /// `styled_prompt` is what it draws. Returns `None` at end of input.
+    pub async fn read_line(&self, prompt: String, styled_prompt: String) -> Result<Option<String>> {
+        let editor = self.editor.clone();
+        let history_path = self.history_path.clone();
+        tokio::task::spawn_blocking(move || -> Result<Option<String>> {
+            let mut editor = editor
+                .lock()
+                .expect("line editor mutex should not be poisoned");
+            if let Some(helper) = editor.helper_mut() {
+                helper.styled_prompt = styled_prompt;
+            }
+
+            let line = match editor.readline(&prompt) {
+                Ok(line) => line,
+                // Ctrl-C discards the line being edited, like a shell.
+                Err(ReadlineError::Interrupted) => return Ok(Some(String::new())),
+                Err(ReadlineError::Eof) => return Ok(None),
+                Err(err) => return Err(err).context("failed to read repl line"),
+            };
+
+            if !line.trim().is_empty()
+                && editor.add_history_entry(line.as_str()).unwrap_or(false)
+                && let Some(path) = history_path.as_ref()
+                && let Err(err) = editor.append_history(path)
+            {
+                warn!("failed to save REPL history to {}: {err}", path.display());
+            }
+            Ok(Some(join_continuation_lines(&line)))
+        })
+        .await
+        .context("failed to join repl input reader")?
+    }
+}
+
+struct ReplHelper {
+    styled_prompt: String,
+    commands: &'static [&'static str],
+}
+
+impl Helper for ReplHelper {}
+
+impl Hinter for ReplHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ReplHelper {
+    fn highlight_prompt<'b, 's: 'b, 'p: 'b>(
+        &'s self,
+        prompt: &'p str,
+        default: bool,
+    ) -> Cow<'b, str> {
+        if default && !self.styled_prompt.is_empty() {
+            Cow::Borrowed(&self.styled_prompt)
+        } else {
+            Cow::Borrowed(prompt)
+        }
+    }
+}
+
+impl Validator for ReplHelper {
+    fn validate(&self, ctx: &mut ValidationContext) -> rustyline::Result<ValidationResult> {
+        Ok(if ends_with_continuation(ctx.input()) {
+            ValidationResult::Incomplete
+        } else {
+            ValidationResult::Valid(None)
+        })
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &rustyline::Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        Ok(complete_line(&line[..pos], self.commands))
+    }
+}
+
+fn complete_line(line: &str, commands: &[&str]) -> (usize, Vec<Pair>) {
+    if line.starts_with(':') && !line.contains(char::is_whitespace) {
+        let candidates = commands
+            .iter()
+            .filter(|command| command.starts_with(line))
+            .map(|command| Pair {
+                display: command.to_string(),
+                // Only `:raw` and `:signal` take an argument; the others must match exactly.
+                replacement: if [RAW_COMMAND, SIGNAL_COMMAND].contains(command) {
+                    format!("{command} ")
+                } else {
+                    command.to_string()
+                },
+            })
+            .collect();
+        return (0, candidates);
+    }
+
+    if let Some(payload) = line.strip_prefix(RAW_COMMAND)
+        && payload.starts_with(char::is_whitespace)
+    {
+        let start = line.rfind(char::is_whitespace).map_or(0, |idx| {
+            idx + line[idx..].chars().next().map_or(1, char::len_utf8)
+        });
+        let word = &line[start..];
+        let name = word.trim_start_matches('\\');
+        let candidates = RAW_ESCAPES
+            .iter()
+            .filter(|(escape_name, spec)| {
+                escape_name.starts_with(name) || (!word.is_empty() && spec.starts_with(word))
+            })
+            .map(|(escape_name, spec)| Pair {
+                display: format!("{escape_name} {spec}"),
+                replacement: spec.to_string(),
+            })
+            .collect();
+        return (start, candidates);
+    }
+
+    if let Some(name) = line.strip_prefix(SIGNAL_COMMAND)
+        && name.starts_with(char::is_whitespace)
+    {
+        let start = line.len() - name.trim_start().len();
+        let name = name.trim_start().to_ascii_uppercase();
+        let candidates = JobSignal::ALL
+            .iter()
+            .filter(|signal| signal.name().starts_with(&name))
+            .map(|signal| Pair {
+                display: signal.name().to_string(),
+                replacement: signal.name().to_string(),
+            })
+            .collect();
+        return (start, candidates);
+    }
+
+    (0, Vec::new())
+}
+
+/// A line ending in an odd number of backslashes continues on the next line.
+fn ends_with_continuation(line: &str) -> bool {
+    let trailing = line.chars().rev().take_while(|ch| *ch == '\\').count();
+    trailing % 2 == 1
+}
+
+/// Drops the backslash from each `\`-newline continuation, keeping the newline.
+fn join_continuation_lines(input: &str) -> String {
+    let mut lines = input.split('\n').peekable();
+    let mut out = String::with_capacity(input.len());
+    while let Some(line) = lines.next() {
+        match lines.peek() {
+            Some(_) if ends_with_continuation(line) => {
+                out.push_str(&line[..line.len() - 1]);
+                out.push('\n');
+            }
+            Some(_) => {
+                out.push_str(line);
+                out.push('\n');
+            }
+            None => out.push_str(line),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{complete_line, ends_with_continuation, join_continuation_lines};
+    use crate::repl::REPL_COMMANDS;
+
+    fn replacements(line: &str) -> (usize, Vec<String>) {
+        let (start, candidates) = complete_line(line, REPL_COMMANDS);
+        (
+            start,
+            candidates
+                .into_iter()
+                .map(|candidate| candidate.replacement)
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn completes_repl_commands() {
+        assert_eq!(
+            replacements(":r"),
+            (0, vec![":raw ".into(), ":reset".into()])
+        );
+        assert_eq!(replacements(":q"), (0, vec![":quit".into()]));
+        assert_eq!(replacements(":si"), (0, vec![":signal ".into()]));
+        assert_eq!(
+            replacements(":signal t"),
+            (8, vec!["TERM".into(), "TSTP".into()])
+        );
+        assert_eq!(replacements("list files"), (0, Vec::new()));
+    }
+
+    #[test]
+    fn completes_raw_escape_names() {
+        assert_eq!(
+            replacements(":raw ls\\r ctrl-c"),
+            (10, vec!["\\x03".into()])
+        );
+        assert_eq!(replacements(":raw \\x1b[A"), (5, vec!["\\x1b[A".into()]));
+        assert!(replacements(":raw ").1.len() > 10);
+    }
+
+    #[test]
+    fn joins_backslash_continuations() {
+        assert!(ends_with_continuation("first line \\"));
+        assert!(!ends_with_continuation(":raw \\\\"));
+        assert_eq!(
+            join_continuation_lines("first \\\nsecond\nthird"),
+            "first \nsecond\nthird"
+        );
+        assert_eq!(join_continuation_lines("a\\\\\nb"), "a\\\\\nb");
+    }
+}
@@ -1,22 +1,52 @@
 mod agent;
+mod attach;
+mod batch;
 mod config;
+mod daemon;
+mod daemon_client;
+mod foreground;
+mod http_api;
+mod landlock_policy;
+mod line_editor;
+mod mcp;
+mod process_tree;
+mod redaction;
 mod repl;
+#[cfg(test)]
+mod replay;
+mod resource_limits;
+mod sandbox;
 mod session_capture;
+mod shell_integration;
+mod terminal_queries;
 mod terminal_session;
+#[cfg(test)]
+mod test_support;
+mod watch;
 
-use anyhow::{Context, Result};
+use anyhow::{Context, Result, ensure};
+use batch::{BatchStatus, OutputFormat};
 use clap::builder::PathBufValueParser;
-use clap::{ArgAction, Parser};
+use clap::{ArgAction, Parser, Subcommand};
+use landlock_policy::LandlockPolicy;
+use redaction::SecretRedactor;
 use repl::ReplOptions;
 use session_capture::SessionCapture;
-use std::io;
+use std::io::{self, IsTerminal, Read};
+use std::net::SocketAddr;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::process::CommandExt;
 use std::path::PathBuf;
+use std::process::{Command, ExitCode};
 use terminal_session::TerminalSession;
 use tracing_subscriber::EnvFilter;
 
 #[derive(Debug, Parser)]
 #[command(name = "gibberish", version, about = "Gibberish CLI")]
 struct Cli {
+    #[command(subcommand)]
+    subcommand: Option<CliCommand>,
+
     /// Increase log verbosity (use -vv for more detail).
     #[arg(short, long, action = ArgAction::Count)]
     verbose: u8,
@@ -29,69 +59,257 @@ struct Cli {
     #[arg(long)]
     yolo: bool,
 
-    /// Execute one REPL line and exit.
+    /// Start the shell in this directory instead of the current one (overrides shell.cwd).
+    #[arg(long, value_parser = PathBufValueParser::new(), value_name = "DIR")]
+    cwd: Option<PathBuf>,
+
+    /// Execute one REPL line and exit; `-` reads the prompt from stdin.
     #[arg(short = 'c', value_name = "COMMAND")]
     command: Option<String>,
 
-    /// Start the configured shell as a login shell.
-    /// Accepted for compatibility; currently a no-op.
+    /// Run `-c COMMAND` in the configured shell when stdin is not a terminal, as sshd, scp and
+    /// sudo expect. Implied by a `-` prefixed argv[0] or a `$SHELL` that points at gibberish.
+    #[arg(long)]
+    shell_compat: bool,
+
+    /// How `-c` reports its result; json also implies agent mode for `-c`.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    output: OutputFormat,
+
+    /// Stop a prompt after this many model turns and exit with the budget-exceeded code.
+    #[arg(long, value_name = "N")]
+    max_turns: Option<usize>,
+
+    /// Start the configured shell as a login shell so it reads the profile files.
+    /// Also implied when gibberish is started with a `-` prefixed argv[0].
     #[arg(short = 'l', long = "login")]
     login: bool,
 
-    /// Force the configured shell into interactive mode.
-    /// Accepted for compatibility; currently a no-op.
+    /// Force the configured shell into interactive mode so it reads its rc file.
     #[arg(short = 'i')]
     interactive: bool,
 
+    /// Let `gibberish watch` show this session (overrides watch.enabled).
+    #[arg(long)]
+    watch: bool,
+
     /// Write a single-file HTML capture of the session history to this path.
     #[arg(long, value_parser = PathBufValueParser::new(), value_name = "PATH")]
     session_html: Option<PathBuf>,
+
+    /// Write a JSONL capture of the session history to this path (replayable in tests).
+    #[arg(long, value_parser = PathBufValueParser::new(), value_name = "PATH")]
+    session_jsonl: Option<PathBuf>,
+}
+
+#[derive(Debug, Subcommand)]
+enum CliCommand {
+    /// Show the terminal of a running session live, read-only.
+    Watch {
+        /// Session pid or socket path (default: the most recently started session).
+        target: Option<String>,
+    },
+    /// Keep the shell and agent running in the background, like a tmux session.
+    Daemon {
+        /// Name to attach to it by.
+        #[arg(default_value = daemon::DEFAULT_DAEMON_NAME)]
+        name: String,
+
+        /// Serve from this process instead of detaching (logs go to stderr).
+        #[arg(long)]
+        foreground: bool,
+    },
+    /// Serve the shell as Model Context Protocol tools over stdio.
+    Mcp,
+    /// Serve the shell and agent over HTTP, authenticated with `serve.token` from the config.
+    Serve {
+        /// Address to listen on, such as 127.0.0.1:8080.
+        #[arg(long, value_name = "ADDR")]
+        listen: SocketAddr,
+    },
+    /// Open a REPL on a running daemon; detaching leaves it running.
+    Attach {
+        /// Daemon name.
+        #[arg(default_value = daemon::DEFAULT_DAEMON_NAME)]
+        name: String,
+    },
+}
+
+fn main() -> Result<ExitCode> {
+    // The sandbox init process must unshare and fork while still single-threaded,
+    // so it is dispatched before clap and the tokio runtime get involved.
+    let mut args = std::env::args_os();
+    if args
+        .nth(1)
+        .is_some_and(|arg| arg == sandbox::SANDBOX_INIT_ARG)
+    {
+        sandbox::run_init(args.collect());
+    }
+
+    run()
 }
 
 #[tokio::main]
-async fn main() -> Result<()> {
-    let cli = Cli::parse();
+async fn run() -> Result<ExitCode> {
+    let mut cli = Cli::parse();
     init_tracing(cli.verbose)?;
-    let options = config::resolve_session_options(cli.config.as_deref())?;
+    if let Some(CliCommand::Watch { target }) = cli.subcommand.as_ref() {
+        watch::watch(target.as_deref()).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    if let Some(CliCommand::Attach { name }) = cli.subcommand.as_ref() {
+        let options = config::resolve_shell_options(cli.config.as_deref())?;
+        daemon_client::attach(name, options.llm.skin).await?;
+        return Ok(ExitCode::SUCCESS);
+    }
+    ensure!(
+        cli.command.is_none() || cli.subcommand.is_none(),
+        "-c cannot be combined with a subcommand"
+    );
+    // Tools that run `$SHELL -c ...` without a terminal expect a real shell, not an agent.
+    let shell_like = cli.command.as_deref().is_some_and(|command| command != "-")
+        && cli.output == OutputFormat::Text
+        && !io::stdin().is_terminal();
+    let shell_passthrough =
+        shell_like && (cli.shell_compat || invoked_as_login_shell() || shell_is_gibberish());
+    if shell_like && !shell_passthrough {
+        tracing::warn!(
+            "stdin is not a terminal, but -c still goes to the agent; \
+             pass --shell-compat to run it in the configured shell"
+        );
+    }
+    // The MCP server never calls the LLM itself; the client is the model.
+    let needs_llm = !shell_passthrough && !matches!(cli.subcommand, Some(CliCommand::Mcp));
+    let mut options = if needs_llm {
+        config::resolve_session_options(cli.config.as_deref())?
+    } else {
+        config::resolve_shell_options(cli.config.as_deref())?
+    };
+    if let Some(cwd) = cli.cwd.clone() {
+        options.shell.cwd = Some(cwd);
+    }
+    options.shell.login |= cli.login || invoked_as_login_shell();
+    options.shell.interactive |= cli.interactive;
+    if shell_passthrough && let Some(command) = cli.command.as_deref() {
+        return Err(exec_shell_command(&options, command));
+    }
+    if cli.command.as_deref() == Some("-") {
+        let mut prompt = String::new();
+        io::stdin()
+            .read_to_string(&mut prompt)
+            .context("failed to read prompt from stdin")?;
+        cli.command = Some(prompt.trim_end().to_string());
+    }
+    let daemon_name = match cli.subcommand.as_ref() {
+        Some(CliCommand::Daemon { name, foreground }) => {
+            if !foreground {
+                daemon::spawn_background(name)?;
+                return Ok(ExitCode::SUCCESS);
+            }
+            Some(name.clone())
+        }
+        _ => None,
+    };
+    let serve_listen = match cli.subcommand.as_ref() {
+        Some(CliCommand::Serve { listen }) => {
+            let token = options
+                .serve
+                .token
+                .clone()
+                .filter(|token| !token.is_empty())
+                .context("set serve.token in the config before running `gibberish serve`")?;
+            Some((*listen, token))
+        }
+        _ => None,
+    };
     let wait_ms = options.wait_ms;
     let yolo = cli.yolo || options.yolo;
     let api_key = options.llm.api_key.clone();
-    let initial_prompt = options.llm.initial_prompt.clone();
+    let base_url = options.llm.base_url.clone();
+    let mut initial_prompt = options.llm.initial_prompt.clone();
+    let landlock = LandlockPolicy::from_config(&options.landlock, &options.shell_cwd()?)?;
+    if let Some(policy) = landlock.as_ref() {
+        initial_prompt = format!("{initial_prompt}\n{}\n", policy.prompt_note());
+    }
     let skin_mode = options.llm.skin;
+    let redactor = SecretRedactor::from_config(&options);
+    let watch_enabled = cli.watch || options.watch.enabled;
     let mut session = TerminalSession::start(options).await?;
-    let session_capture = cli.session_html.as_ref().map(|_| SessionCapture::new());
+    // Watchers get the raw PTY output, before redaction, so this is opt-in.
+    let watch_server = watch_enabled
+        .then(|| {
+            watch::WatchServer::start(session.handle())
+                .inspect(|server| tracing::info!("watch socket: {}", server.path().display()))
+                .inspect_err(|err| tracing::warn!("live view unavailable: {err:#}"))
+                .ok()
+        })
+        .flatten();
+    let session_capture = (cli.session_html.is_some()
+        || cli.session_jsonl.is_some()
+        || daemon_name.is_some()
+        || serve_listen.is_some())
+    .then(SessionCapture::new);
+    let repl_options = ReplOptions {
+        wait_ms,
+        initial_prompt: &initial_prompt,
+        skin_mode,
+        verbose: cli.verbose,
+        api_key: &api_key,
+        base_url: base_url.as_deref(),
+        yolo,
+        redactor,
+        max_turns: cli.max_turns,
+    };
 
-    let repl_result = if let Some(command) = cli.command.as_deref() {
-        repl::run_single_command(
+    let repl_result = if let Some(name) = daemon_name.as_deref() {
+        daemon::run_daemon(
             &session,
-            ReplOptions {
-                wait_ms,
-                initial_prompt: &initial_prompt,
-                skin_mode,
-                verbose: cli.verbose,
-                api_key: &api_key,
-                yolo,
-            },
-            command,
+            name,
+            repl_options,
+            session_capture
+                .clone()
+                .expect("daemons and servers always capture the session"),
+        )
+        .await
+        .map(|()| BatchStatus::Success)
+    } else if let Some((listen, token)) = serve_listen {
+        http_api::run_serve(
+            &session,
+            listen,
+            token,
+            repl_options,
+            session_capture
+                .clone()
+                .expect("daemons and servers always capture the session"),
+        )
+        .await
+        .map(|()| BatchStatus::Success)
+    } else if let Some(CliCommand::Mcp) = cli.subcommand {
+        mcp::run_mcp(
+            &session,
+            repl_options.yolo,
+            repl_options.redactor,
+            landlock.as_ref(),
             session_capture.clone(),
         )
         .await
-    } else {
-        repl::run_repl(
+        .map(|()| BatchStatus::Success)
+    } else if let Some(command) = cli.command.as_deref() {
+        repl::run_single_command(
             &session,
-            ReplOptions {
-                wait_ms,
-                initial_prompt: &initial_prompt,
-                skin_mode,
-                verbose: cli.verbose,
-                api_key: &api_key,
-                yolo,
-            },
+            repl_options,
+            command,
             session_capture.clone(),
+            cli.output,
         )
         .await
+    } else {
+        repl::run_repl(&session, repl_options, session_capture.clone())
+            .await
+            .map(|()| BatchStatus::Success)
     };
 
+    drop(watch_server);
     let shutdown_result = session
         .shutdown()
         .await
@@ -103,12 +321,60 @@ async fn main() -> Result<()> {
             .with_context(|| format!("failed to write session capture HTML to {}", path.display())),
         _ => Ok(()),
     };
+    let capture_jsonl_result = match (&session_capture, cli.session_jsonl.as_deref()) {
+        (Some(capture), Some(path)) => capture.write_jsonl(path).with_context(|| {
+            format!(
+                "failed to write session capture JSONL to {}",
+                path.display()
+            )
+        }),
+        _ => Ok(()),
+    };
 
-    repl_result?;
+    let status = repl_result?;
     shutdown_result?;
     capture_write_result?;
+    capture_jsonl_result?;
+
+    Ok(status.exit_code())
+}
+
+fn invoked_as_login_shell() -> bool {
+    std::env::args_os()
+        .next()
+        .is_some_and(|arg0| arg0.as_bytes().starts_with(b"-"))
+}
 
-    Ok(())
+/// Whether `$SHELL` names this executable, as it does for a user whose login shell is gibberish.
+fn shell_is_gibberish() -> bool {
+    let Some(shell) = std::env::var_os("SHELL") else {
+        return false;
+    };
+    match (std::fs::canonicalize(shell), std::env::current_exe()) {
+        (Ok(shell), Ok(exe)) => std::fs::canonicalize(exe).is_ok_and(|exe| exe == shell),
+        _ => false,
+    }
+}
+
+/// Replaces this process with `shell.program -c COMMAND`; only returns on failure.
+fn exec_shell_command(options: &config::SessionConfig, command: &str) -> anyhow::Error {
+    let cwd = match options.shell_cwd() {
+        Ok(cwd) => cwd,
+        Err(err) => return err,
+    };
+    let mut shell = Command::new(&options.shell.program);
+    if let Some(arg0) = options.shell.arg0() {
+        shell.arg0(arg0);
+    }
+    let err = shell
+        .args(options.shell.effective_args())
+        .arg("-c")
+        .arg(command)
+        .current_dir(cwd)
+        .env_clear()
+        .envs(options.shell.environment())
+        .exec();
+    anyhow::Error::new(err).context(format!("failed to exec {}", options.shell.program))
 }
 
 fn init_tracing(verbose: u8) -> Result<()> {
@@ -0,0 +1,505 @@
+use anyhow::{Context, Result, bail};
+use serde_json::{Value, json};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, warn};
+
+use crate::agent::{ApprovalRequest, TerminalInput, raw_input_definition};
+use crate::landlock_policy::LandlockPolicy;
+use crate::redaction::{REDACTION_PROMPT_NOTE, SecretRedactor};
+use crate::session_capture::SessionCapture;
+use crate::terminal_session::{TerminalSession, TerminalSessionHandle};
+
+const PROTOCOL_VERSIONS: &[&str] = &["2025-06-18", "2025-03-26", "2024-11-05"];
+const PARSE_ERROR: i64 = -32700;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+
+/// Serves the session's terminal as MCP tools over stdin and stdout until stdin closes.
+pub async fn run_mcp(
+    session: &TerminalSession,
+    yolo: bool,
+    redactor: Option<SecretRedactor>,
+    landlock: Option<&LandlockPolicy>,
+    session_capture: Option<SessionCapture>,
+) -> Result<()> {
+    let instructions = instructions(redactor.is_some(), landlock);
+    let terminal = TerminalInput::new(session.handle(), yolo, session_capture, redactor);
+    serve(
+        tokio::io::stdin(),
+        tokio::io::stdout(),
+        session.handle(),
+        terminal,
+        instructions,
+    )
+    .await
+}
+
+/// The client is the model here, so it gets the notes the built-in agent's preamble has.
+fn instructions(redacted: bool, landlock: Option<&LandlockPolicy>) -> String {
+    let mut instructions = "These tools drive one persistent interactive shell in a PTY. \
+        Send keystrokes with raw_input and read the screen it returns."
+        .to_string();
+    if redacted {
+        instructions.push(' ');
+        instructions.push_str(REDACTION_PROMPT_NOTE);
+    }
+    if let Some(policy) = landlock {
+        instructions.push(' ');
+        instructions.push_str(&policy.prompt_note());
+    }
+    instructions
+}
+
+async fn serve(
+    input: impl AsyncRead + Unpin,
+    mut output: impl AsyncWrite + Unpin + Send + 'static,
+    session: TerminalSessionHandle,
+    terminal: TerminalInput,
+    instructions: String,
+) -> Result<()> {
+    let (outgoing, mut messages) = mpsc::unbounded_channel::<Value>();
+    let write_loop = tokio::spawn(async move {
+        while let Some(message) = messages.recv().await {
+            let mut line = message.to_string();
+            line.push('\n');
+            output
+                .write_all(line.as_bytes())
+                .await
+                .context("failed to write MCP message")?;
+            output.flush().await.context("failed to flush MCP output")?;
+        }
+        anyhow::Ok(())
+    });
+
+    let (approver, mut approvals) = mpsc::unbounded_channel();
+    terminal.set_approver(approver);
+    let server = Arc::new(McpServer {
+        session,
+        terminal,
+        outgoing,
+        next_request_id: AtomicU64::new(1),
+        pending: Mutex::default(),
+        elicitation: AtomicBool::new(false),
+        instructions,
+    });
+    let approval_loop = tokio::spawn({
+        let server = server.clone();
+        async move {
+            while let Some(request) = approvals.recv().await {
+                tokio::spawn(server.clone().approve(request));
+            }
+        }
+    });
+
+    let mut lines = BufReader::new(input).lines();
+    let result = async {
+        while let Some(line) = lines
+            .next_line()
+            .await
+            .context("failed to read MCP input")?
+        {
+            if !line.trim().is_empty() {
+                server.handle_line(&line);
+            }
+        }
+        Ok(())
+    }
+    .await;
+
+    approval_loop.abort();
+    // Nobody is left to answer our questions, which denies whatever they were about.
+    server
+        .pending
+        .lock()
+        .expect("pending requests mutex should not be poisoned")
+        .clear();
+    drop(server);
+    // Let calls still running reply before exiting.
+    let _ = write_loop.await;
+    result
+}
+
+struct McpServer {
+    session: TerminalSessionHandle,
+    terminal: TerminalInput,
+    outgoing: mpsc::UnboundedSender<Value>,
+    next_request_id: AtomicU64,
+    pending: Mutex<HashMap<u64, oneshot::Sender<Value>>>,
+    /// Whether the client can ask its user questions, which is how approvals are asked.
+    elicitation: AtomicBool,
+    instructions: String,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+}
+
+impl McpServer {
+    fn send(&self, message: Value) {
+        let _ = self.outgoing.send(message);
+    }
+
+    fn handle_line(self: &Arc<Self>, line: &str) {
+        let message: Value = match serde_json::from_str(line) {
+            Ok(message) => message,
+            Err(err) => {
+                self.send(json!({
+                    "jsonrpc": "2.0",
+                    "id": null,
+                    "error": {"code": PARSE_ERROR, "message": format!("invalid JSON: {err}")},
+                }));
+                return;
+            }
+        };
+
+        let Some(method) = message.get("method").and_then(Value::as_str) else {
+            // A reply to one of our own requests.
+            if let Some(id) = message.get("id").and_then(Value::as_u64)
+                && let Some(reply) = self
+                    .pending
+                    .lock()
+                    .expect("pending requests mutex should not be poisoned")
+                    .remove(&id)
+            {
+                let _ = reply.send(message);
+            }
+            return;
+        };
+        let Some(id) = message.get("id").cloned() else {
+            debug!("ignoring MCP notification {method}");
+            return;
+        };
+
+        let server = self.clone();
+        let method = method.to_string();
+        let params = message.get("params").cloned().unwrap_or(Value::Null);
+        tokio::spawn(async move {
+            let reply = match server.handle_request(&method, params).await {
+                Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+                Err(err) => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {"code": err.code, "message": err.message},
+                }),
+            };
+            server.send(reply);
+        });
+    }
+
+    async fn handle_request(&self, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "initialize" => Ok(self.initialize(&params)),
+            "ping" => Ok(json!({})),
+            "tools/list" => Ok(json!({"tools": tool_definitions()})),
+            "tools/call" => {
+                let name = params
+                    .get("name")
+                    .and_then(Value::as_str)
+                    .ok_or_else(|| RpcError::new(INVALID_PARAMS, "tools/call needs a name"))?;
+                let arguments = params.get("arguments").cloned().unwrap_or(json!({}));
+                let result = match name {
+                    "raw_input" => self.terminal.run_raw_input_tool(arguments).await,
+                    "snapshot" => self.snapshot(&arguments).await,
+                    "reset" => self.reset(&arguments).await,
+                    "resize" => self.resize(&arguments).await,
+                    _ => {
+                        return Err(RpcError::new(
+                            INVALID_PARAMS,
+                            format!("unknown tool `{name}`"),
+                        ));
+                    }
+                };
+                Ok(match result {
+                    Ok(text) => {
+                        json!({"content": [{"type": "text", "text": text}], "isError": false})
+                    }
+                    Err(err) => json!({
+                        "content": [{"type": "text", "text": format!("{err:#}")}],
+                        "isError": true,
+                    }),
+                })
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("unknown method `{method}`"),
+            )),
+        }
+    }
+
+    fn initialize(&self, params: &Value) -> Value {
+        let requested = params.get("protocolVersion").and_then(Value::as_str);
+        let version = requested
+            .filter(|version| PROTOCOL_VERSIONS.contains(version))
+            .unwrap_or(PROTOCOL_VERSIONS[0]);
+        let elicitation = params
+            .pointer("/capabilities/elicitation")
+            .is_some_and(|capability| !capability.is_null());
+        self.elicitation.store(elicitation, Ordering::Relaxed);
+
+        json!({
+            "protocolVersion": version,
+            "capabilities": {"tools": {}},
+            "serverInfo": {"name": "gibberish", "version": env!("CARGO_PKG_VERSION")},
+            "instructions": self.instructions,
+        })
+    }
+
+    async fn snapshot(&self, arguments: &Value) -> Result<String> {
+        self.terminal.screen_tool_call("snapshot", arguments).await
+    }
+
+    async fn reset(&self, arguments: &Value) -> Result<String> {
+        self.terminal.reset_shell("reset", arguments).await
+    }
+
+    async fn resize(&self, arguments: &Value) -> Result<String> {
+        let dimension = |name: &str| -> Result<u16> {
+            let value = arguments
+                .get(name)
+                .and_then(Value::as_u64)
+                .with_context(|| format!("resize needs a positive integer `{name}`"))?;
+            match u16::try_from(value) {
+                Ok(value) if value > 0 => Ok(value),
+                _ => bail!("`{name}` must be between 1 and {}", u16::MAX),
+            }
+        };
+        self.session
+            .resize(dimension("cols")?, dimension("rows")?)
+            .await?;
+        self.terminal.screen_tool_call("resize", arguments).await
+    }
+
+    async fn approve(self: Arc<Self>, request: ApprovalRequest) {
+        let allowed = if self.elicitation.load(Ordering::Relaxed) {
+            self.elicit_approval(&request)
+                .await
+                .inspect_err(|err| warn!("approval request failed: {err:#}"))
+                .unwrap_or(false)
+        } else {
+            warn!(
+                "denied `{}`: the MCP client cannot ask for approval; run with --yolo to allow tool calls",
+                request.tool_name
+            );
+            false
+        };
+        let _ = request.reply.send(allowed);
+    }
+
+    async fn elicit_approval(&self, request: &ApprovalRequest) -> Result<bool> {
+        let mut message = format!(
+            "Allow `{}` on the gibberish terminal?\ninput: {}",
+            request.tool_name, request.input
+        );
+        if !request.bytes.is_empty() {
+            message.push_str(&format!("\nbytes: {}", request.bytes));
+        }
+        let reply = self
+            .request(
+                "elicitation/create",
+                json!({
+                    "message": message,
+                    "requestedSchema": {
+                        "type": "object",
+                        "properties": {"allow": {"type": "boolean", "title": "Allow"}},
+                        "required": ["allow"],
+                    },
+                }),
+            )
+            .await?;
+        if let Some(error) = reply.get("error") {
+            bail!("client refused to ask: {error}");
+        }
+        let result = reply
+            .get("result")
+            .context("elicitation reply has no result")?;
+        Ok(result["action"] == "accept" && result["content"]["allow"] == true)
+    }
+
+    async fn request(&self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_request_id.fetch_add(1, Ordering::Relaxed);
+        let (reply, response) = oneshot::channel();
+        self.pending
+            .lock()
+            .expect("pending requests mutex should not be poisoned")
+            .insert(id, reply);
+        self.send(json!({"jsonrpc": "2.0", "id": id, "method": method, "params": params}));
+        response
+            .await
+            .with_context(|| format!("no reply to {method}"))
+    }
+}
+
+fn tool_definitions() -> Value {
+    let raw_input = raw_input_definition();
+    let no_arguments = json!({"type": "object", "properties": {}, "additionalProperties": false});
+    json!([
+        {
+            "name": raw_input.name,
+            "description": raw_input.description,
+            "inputSchema": raw_input.parameters,
+        },
+        {
+            "name": "snapshot",
+            "description": "Return the current terminal screen without sending anything.",
+            "inputSchema": no_arguments,
+        },
+        {
+            "name": "reset",
+            "description": "Kill the shell and start a fresh one. Returns the new screen.",
+            "inputSchema": no_arguments,
+        },
+        {
+            "name": "resize",
+            "description": "Resize the terminal to cols x rows. Returns the redrawn screen.",
+            "inputSchema": {
+                "type": "object",
+                "properties": {
+                    "cols": {"type": "integer", "minimum": 1},
+                    "rows": {"type": "integer", "minimum": 1},
+                },
+                "required": ["cols", "rows"],
+                "additionalProperties": false,
+            },
+        },
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{instructions, serve};
+    use crate::agent::TerminalInput;
+    use crate::landlock_policy::LandlockPolicy;
+    use crate::session_capture::SessionCapture;
+    use crate::terminal_session::TerminalSession;
+    use crate::test_support::{shell_session_config, wait_for_prompt};
+    use serde_json::{Value, json};
+    use std::path::PathBuf;
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+
+    #[tokio::test]
+    async fn tool_calls_are_approved_through_elicitation() {
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        let capture = SessionCapture::new();
+        let terminal = TerminalInput::new(session.handle(), false, Some(capture.clone()), None);
+        let (client_in, server_out) = tokio::io::duplex(64 * 1024);
+        let (server_in, mut client_out) = tokio::io::duplex(64 * 1024);
+        let server = tokio::spawn(serve(
+            server_in,
+            server_out,
+            session.handle(),
+            terminal,
+            instructions(false, None),
+        ));
+        let mut replies = BufReader::new(client_in).lines();
+        let mut send = async |message: Value| {
+            let mut line = message.to_string();
+            line.push('\n');
+            client_out
+                .write_all(line.as_bytes())
+                .await
+                .expect("send message");
+        };
+
+        send(
+            json!({"jsonrpc": "2.0", "id": 1, "method": "initialize", "params": {
+                "protocolVersion": "2025-06-18",
+                "capabilities": {"elicitation": {}},
+                "clientInfo": {"name": "test", "version": "0"},
+            }}),
+        )
+        .await;
+        send(json!({"jsonrpc": "2.0", "method": "notifications/initialized"})).await;
+        send(json!({"jsonrpc": "2.0", "id": 2, "method": "tools/list"})).await;
+        send(
+            json!({"jsonrpc": "2.0", "id": 3, "method": "tools/call", "params": {
+                "name": "raw_input",
+                "arguments": {"str": "echo mcp\\r", "float": 0.3},
+            }}),
+        )
+        .await;
+
+        let mut results = std::collections::BTreeMap::new();
+        while results.len() < 3 {
+            let line = replies
+                .next_line()
+                .await
+                .expect("read reply")
+                .expect("server open");
+            let message: Value = serde_json::from_str(&line).expect("parse reply");
+            if message["method"] == "elicitation/create" {
+                assert!(
+                    message["params"]["message"]
+                        .as_str()
+                        .expect("question")
+                        .contains("echo mcp\\r")
+                );
+                send(json!({"jsonrpc": "2.0", "id": message["id"], "result": {
+                    "action": "accept",
+                    "content": {"allow": true},
+                }}))
+                .await;
+            } else {
+                results.insert(message["id"].as_u64().expect("reply id"), message);
+            }
+        }
+        drop(client_out);
+        server.await.expect("join server").expect("serve");
+        session.shutdown().await.expect("shutdown session");
+
+        assert_eq!(results[&1]["result"]["protocolVersion"], "2025-06-18");
+        let tools: Vec<&str> = results[&2]["result"]["tools"]
+            .as_array()
+            .expect("tools")
+            .iter()
+            .filter_map(|tool| tool["name"].as_str())
+            .collect();
+        assert_eq!(tools, ["raw_input", "snapshot", "reset", "resize"]);
+        let call = &results[&3]["result"];
+        assert_eq!(call["isError"], false);
+        assert!(
+            call["content"][0]["text"]
+                .as_str()
+                .expect("screen")
+                .contains("$ echo mcp")
+        );
+        assert!(
+            capture
+                .render_jsonl()
+                .expect("render capture")
+                .contains(r#""tool_name":"raw_input""#)
+        );
+    }
+
+    #[test]
+    fn instructions_explain_redaction_and_landlock() {
+        let policy = LandlockPolicy {
+            read: vec![PathBuf::from("/usr")],
+            write: vec![PathBuf::from("/work")],
+        };
+
+        let plain = instructions(false, None);
+        assert!(!plain.contains("REDACTED"), "{plain}");
+        assert!(!plain.contains("Landlock"), "{plain}");
+        let noted = instructions(true, Some(&policy));
+        assert!(noted.contains("[REDACTED:secret-1]"), "{noted}");
+        assert!(noted.contains("Landlock"), "{noted}");
+        assert!(noted.contains("/work"), "{noted}");
+    }
+}
@@ -0,0 +1,206 @@
+use anyhow::{Context, Result};
+use rustix::param::{clock_ticks_per_second, page_size};
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::fs;
+use std::time::Duration;
+
+use crate::foreground::{ProcStat, namespace_pid};
+
+/// Processes past this many are counted but not listed.
+const MAX_PROCESSES: usize = 200;
+/// Command lines are cut to this many characters.
+const MAX_COMMAND_LINE: usize = 200;
+
+/// A process started by the shell, directly or not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessInfo {
+    /// As seen from inside the shell's PID namespace.
+    pub pid: i32,
+    /// 0 for the shell, 1 for its children, and so on.
+    pub depth: usize,
+    pub state: char,
+    /// In the terminal's foreground process group.
+    pub foreground: bool,
+    pub cpu: Option<Duration>,
+    pub rss_bytes: Option<u64>,
+    pub command: String,
+}
+
+/// The shell and everything under it, parents before their children.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProcessTree {
+    pub processes: Vec<ProcessInfo>,
+    /// Left out past `MAX_PROCESSES`.
+    pub omitted: usize,
+}
+
+impl ProcessTree {
+    /// Walks `/proc` down from `spawned`, the process gibberish started. When `sandboxed`
+    /// that is the sandbox init, which is left out in favour of the shell under it.
+    /// `foreground` is the terminal's foreground process group.
+    pub fn read(spawned: i32, sandboxed: bool, foreground: Option<i32>) -> Result<Self> {
+        let mut stats = HashMap::new();
+        let mut children: HashMap<i32, Vec<i32>> = HashMap::new();
+        for entry in fs::read_dir("/proc").context("failed to read /proc")? {
+            let Some(pid) = entry
+                .ok()
+                .and_then(|entry| entry.file_name().to_str()?.parse::<i32>().ok())
+            else {
+                continue;
+            };
+            // Processes can exit while the directory is read.
+            if let Some(stat) = ProcStat::read(pid) {
+                children.entry(stat.parent).or_default().push(pid);
+                stats.insert(pid, stat);
+            }
+        }
+        for pids in children.values_mut() {
+            pids.sort_unstable();
+        }
+
+        let mut pending: Vec<(i32, usize)> = if sandboxed {
+            children
+                .get(&spawned)
+                .into_iter()
+                .flatten()
+                .rev()
+                .map(|&pid| (pid, 0))
+                .collect()
+        } else {
+            vec![(spawned, 0)]
+        };
+        let mut tree = Self {
+            processes: Vec::new(),
+            omitted: 0,
+        };
+        let ticks_per_second = clock_ticks_per_second();
+        while let Some((pid, depth)) = pending.pop() {
+            let Some(stat) = stats.get(&pid) else {
+                continue;
+            };
+            pending.extend(
+                children
+                    .get(&pid)
+                    .into_iter()
+                    .flatten()
+                    .rev()
+                    .map(|&child| (child, depth + 1)),
+            );
+            if tree.processes.len() == MAX_PROCESSES {
+                tree.omitted += 1;
+                continue;
+            }
+            tree.processes.push(ProcessInfo {
+                pid: namespace_pid(pid).unwrap_or(pid),
+                depth,
+                state: stat.state,
+                foreground: foreground == Some(stat.group),
+                cpu: stat
+                    .cpu_ticks
+                    .filter(|_| ticks_per_second > 0)
+                    .map(|ticks| Duration::from_secs_f64(ticks as f64 / ticks_per_second as f64)),
+                rss_bytes: stat.rss_pages.map(|pages| pages * page_size() as u64),
+                command: command_line(pid).unwrap_or_else(|| format!("[{}]", stat.name)),
+            });
+        }
+        Ok(tree)
+    }
+
+    /// A `ps`-like table, with commands indented under their parents.
+    pub fn render(&self) -> String {
+        let mut out = String::from("  PID STAT      CPU      RSS  COMMAND\n");
+        for process in &self.processes {
+            let state = format!(
+                "{}{}",
+                process.state,
+                if process.foreground { "+" } else { "" }
+            );
+            let cpu = process.cpu.map_or_else(
+                || "?".to_string(),
+                |cpu| format!("{:.2}s", cpu.as_secs_f64()),
+            );
+            let rss = process
+                .rss_bytes
+                .map_or_else(|| "?".to_string(), format_bytes);
+            let _ = writeln!(
+                out,
+                "{:>5} {state:<4} {cpu:>8} {rss:>8}  {}{}",
+                process.pid,
+                "  ".repeat(process.depth),
+                process.command
+            );
+        }
+        if self.omitted > 0 {
+            let _ = writeln!(out, "[{} more processes not shown.]", self.omitted);
+        }
+        out.push_str("STAT is R running, S sleeping, D in uninterruptible I/O, T stopped, Z zombie; + marks the foreground job.");
+        out
+    }
+}
+
+/// `None` for zombies and kernel threads, which have no command line.
+fn command_line(pid: i32) -> Option<String> {
+    let raw = fs::read(format!("/proc/{pid}/cmdline")).ok()?;
+    let line = String::from_utf8_lossy(&raw)
+        .split('\0')
+        .filter(|arg| !arg.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    if line.is_empty() {
+        return None;
+    }
+    Some(match line.char_indices().nth(MAX_COMMAND_LINE) {
+        Some((cut, _)) => format!("{}...", &line[..cut]),
+        None => line,
+    })
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["K", "M", "G", "T"];
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while size >= 1024.0 && unit + 1 < UNITS.len() {
+        size /= 1024.0;
+        unit += 1;
+    }
+    format!("{size:.1}{}", UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ProcessInfo, ProcessTree};
+    use std::time::Duration;
+
+    #[test]
+    fn renders_an_indented_table() {
+        let shell = ProcessInfo {
+            pid: 1,
+            depth: 0,
+            state: 'S',
+            foreground: false,
+            cpu: Some(Duration::from_millis(20)),
+            rss_bytes: Some(4 * 1024 * 1024),
+            command: "bash --norc".to_string(),
+        };
+        let job = ProcessInfo {
+            pid: 27,
+            depth: 1,
+            state: 'R',
+            foreground: true,
+            cpu: Some(Duration::from_secs(75)),
+            rss_bytes: Some(3 * 1024 * 1024 * 1024 / 2),
+            command: "python train.py".to_string(),
+        };
+        let tree = ProcessTree {
+            processes: vec![shell, job],
+            omitted: 3,
+        };
+
+        let rendered = tree.render();
+        let lines: Vec<&str> = rendered.lines().collect();
+        assert_eq!(lines[1], "    1 S       0.02s     4.0M  bash --norc");
+        assert_eq!(lines[2], "   27 R+     75.00s     1.5G    python train.py");
+        assert_eq!(lines[3], "[3 more processes not shown.]");
+    }
+}
@@ -1,22 +1,33 @@
-use anyhow::{Context, Result};
-use std::io::{self, Write};
+use anyhow::{Context, Result, ensure};
 use std::time::Duration;
 use termimad::{MadSkin, terminal_size};
 use time::OffsetDateTime;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
 use crate::agent::AgentRuntime;
-use crate::config::SkinMode;
+use crate::attach::{DETACH_KEY_NAME, attach};
+use crate::batch::{BatchReport, BatchStatus, OutputFormat, TokenUsage};
+use crate::config::{SkinMode, default_state_dir};
+use crate::line_editor::ReplEditor;
+use crate::redaction::SecretRedactor;
 use crate::session_capture::SessionCapture;
 use crate::terminal_session::{TerminalSession, TerminalSnapshot};
 
+/// Commands handled by the REPL itself rather than sent to the agent.
+pub const REPL_COMMANDS: &[&str] = &[
+    ":raw", ":signal", ":snap", ":attach", ":reset", ":help", ":quit",
+];
+
 pub struct ReplOptions<'a> {
     pub wait_ms: u64,
     pub initial_prompt: &'a str,
     pub skin_mode: SkinMode,
     pub verbose: u8,
     pub api_key: &'a str,
+    pub base_url: Option<&'a str>,
     pub yolo: bool,
+    pub redactor: Option<SecretRedactor>,
+    pub max_turns: Option<usize>,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -34,22 +45,35 @@ pub async fn run_repl(
         session.handle(),
         options.initial_prompt,
         options.api_key,
+        options.base_url,
         options.yolo,
         session_capture.clone(),
+        options.redactor,
     )?;
+    if let Some(max_turns) = options.max_turns {
+        agent_runtime.set_max_turns(max_turns);
+    }
     let line_exec_config = LineExecutionConfig {
         verbose: options.verbose,
         default_wait_seconds: Duration::from_millis(options.wait_ms).as_secs_f64(),
     };
     let skin = resolve_skin(options.skin_mode);
     let mut last_response_total_tokens: Option<u64> = None;
+    let state_dir = default_state_dir()
+        .inspect_err(|err| warn!("not saving REPL history: {err:#}"))
+        .ok();
+    let editor = ReplEditor::new(state_dir, REPL_COMMANDS)?;
 
-    info!("interactive mode: prompts go to agent; commands: :raw, :snap, :reset, :help, :quit");
+    info!(
+        "interactive mode: prompts go to agent; commands: :raw, :signal, :snap, :attach, :reset, :help, :quit"
+    );
 
     loop {
-        print_repl_prompt(&skin, last_response_total_tokens)?;
-
-        let Some(line) = read_repl_line().await? else {
+        // The prompt just goes without a directory when the snapshot fails.
+        let terminal = session.snapshot().await.ok();
+        let (prompt, styled_prompt) =
+            repl_prompt(&skin, last_response_total_tokens, terminal.as_ref());
+        let Some(line) = editor.read_line(prompt, styled_prompt).await? else {
             break;
         };
 
@@ -76,31 +100,127 @@ pub async fn run_single_command(
     options: ReplOptions<'_>,
     line: &str,
     session_capture: Option<SessionCapture>,
-) -> Result<()> {
+    output: OutputFormat,
+) -> Result<BatchStatus> {
     let mut agent_runtime = AgentRuntime::new(
         session.handle(),
         options.initial_prompt,
         options.api_key,
+        options.base_url,
         options.yolo,
         session_capture.clone(),
+        options.redactor,
     )?;
-    let line_exec_config = LineExecutionConfig {
-        verbose: options.verbose,
-        default_wait_seconds: Duration::from_millis(options.wait_ms).as_secs_f64(),
-    };
+    if let Some(max_turns) = options.max_turns {
+        agent_runtime.set_max_turns(max_turns);
+    }
     let skin = resolve_skin(options.skin_mode);
-    let mut last_response_total_tokens = None;
-    process_line(
-        session,
-        &mut agent_runtime,
-        line_exec_config,
-        session_capture.as_ref(),
-        &skin,
-        line,
-        &mut last_response_total_tokens,
-    )
-    .await?;
-    Ok(())
+    let trimmed = line.trim_end_matches(&['\r', '\n'][..]);
+
+    if trimmed.is_empty() || trimmed.starts_with(':') {
+        ensure!(
+            output == OutputFormat::Text,
+            "REPL commands are not supported with --output json"
+        );
+        let line_exec_config = LineExecutionConfig {
+            verbose: options.verbose,
+            default_wait_seconds: Duration::from_millis(options.wait_ms).as_secs_f64(),
+        };
+        let mut last_response_total_tokens = None;
+        process_line(
+            session,
+            &mut agent_runtime,
+            line_exec_config,
+            session_capture.as_ref(),
+            &skin,
+            trimmed,
+            &mut last_response_total_tokens,
+        )
+        .await?;
+        return Ok(BatchStatus::Success);
+    }
+
+    if let Some(capture) = session_capture.as_ref() {
+        capture.record_user_input(trimmed);
+    }
+    let report = run_batch_prompt(&mut agent_runtime, trimmed, session_capture.as_ref()).await;
+    match output {
+        OutputFormat::Text => {
+            if let Some(response) = report.response.as_deref() {
+                print_agent_response(&skin, response);
+            }
+            if let Some(error) = report.error.as_deref() {
+                eprintln!("agent error: {error}");
+            }
+        }
+        OutputFormat::Json => println!(
+            "{}",
+            serde_json::to_string_pretty(&report).context("failed to serialize batch report")?
+        ),
+    }
+    Ok(report.status)
+}
+
+async fn run_batch_prompt(
+    agent_runtime: &mut AgentRuntime,
+    prompt: &str,
+    session_capture: Option<&SessionCapture>,
+) -> BatchReport {
+    let result = agent_runtime.prompt(prompt).await;
+    let stats = agent_runtime.tool_call_stats();
+
+    let (mut status, response, mut error, usage, finish) = match result {
+        Ok(response) => {
+            if let Some(capture) = session_capture {
+                capture.record_assistant_response(&response.output);
+            }
+            let status = BatchStatus::completed(
+                response.finish.as_ref().map(|task| task.status),
+                stats.denied,
+            );
+            let usage = TokenUsage {
+                input_tokens: response.input_tokens,
+                output_tokens: response.output_tokens,
+                total_tokens: response.total_tokens,
+            };
+            (status, Some(response.output), None, usage, response.finish)
+        }
+        // A failed prompt still spent tokens, and for a blown budget that is what matters.
+        Err(err) => (
+            BatchStatus::from_error(&err),
+            None,
+            Some(format!("{err:#}")),
+            TokenUsage::from(agent_runtime.last_usage()),
+            None,
+        ),
+    };
+
+    // A dead shell still gets a report, with the reason in `error`.
+    let final_screen = match agent_runtime.render_screen().await {
+        Ok(screen) => screen,
+        Err(err) => {
+            let failure = format!("failed to capture the final screen: {err:#}");
+            error = Some(match error {
+                Some(error) => format!("{error}; {failure}"),
+                None => failure,
+            });
+            if status == BatchStatus::Success {
+                status = BatchStatus::AgentError;
+            }
+            String::new()
+        }
+    };
+
+    BatchReport {
+        status,
+        response,
+        error,
+        usage,
+        tool_calls: stats.tool_calls,
+        denied_tool_calls: stats.denied,
+        finish,
+        final_screen,
+    }
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -130,7 +250,7 @@ async fn process_line(
         ":quit" | ":q" => return Ok(LineControl::Quit),
         ":help" => {
             eprintln!(
-                "commands: :raw <spec> (send escaped bytes), :snap (snapshot now), :reset (restart shell + clear agent state), :quit (exit). every other line is sent to the agent"
+                "commands: :raw <spec> (send escaped bytes), :signal <INT|TERM|KILL|TSTP|QUIT> (signal the foreground job), :snap (snapshot now), :attach (type into the shell yourself), :reset (restart shell + clear agent state), :quit (exit). every other line is sent to the agent"
             );
             return Ok(LineControl::Continue);
         }
@@ -139,6 +259,17 @@ async fn process_line(
             print_snapshot(&snapshot, config.verbose);
             return Ok(LineControl::Continue);
         }
+        ":attach" => {
+            eprintln!("attaching to the shell; press {DETACH_KEY_NAME} to return");
+            match attach(&session.handle()).await {
+                Ok(duration) => {
+                    eprintln!("\r\ndetached");
+                    agent_runtime.note_human_takeover(duration).await?;
+                }
+                Err(err) => eprintln!("\r\ncommand error: {err:#}"),
+            }
+            return Ok(LineControl::Continue);
+        }
         ":reset" => {
             session.reset().await?;
             agent_runtime.reset();
@@ -182,10 +313,13 @@ async fn process_line(
 #[derive(Debug, PartialEq, Eq)]
 enum PrefixedCommand {
     Raw(String),
+    Signal(String),
 }
 
 fn parse_prefixed_command(line: &str) -> Option<PrefixedCommand> {
-    parse_prefixed_arg(line, ":raw").map(PrefixedCommand::Raw)
+    parse_prefixed_arg(line, ":raw")
+        .map(PrefixedCommand::Raw)
+        .or_else(|| parse_prefixed_arg(line, ":signal").map(PrefixedCommand::Signal))
 }
 
 fn parse_prefixed_arg(line: &str, prefix: &str) -> Option<String> {
@@ -208,6 +342,7 @@ async fn execute_prefixed_command(
 ) -> Result<String> {
     match command {
         PrefixedCommand::Raw(spec) => agent_runtime.send_raw_input(&spec, wait_seconds).await,
+        PrefixedCommand::Signal(name) => agent_runtime.send_signal(&name, wait_seconds).await,
     }
 }
 
@@ -233,25 +368,41 @@ pub fn print_snapshot(snapshot: &TerminalSnapshot, verbose: u8) {
     println!("{}", snapshot.render());
 }
 
-fn print_repl_prompt(skin: &MadSkin, last_response_total_tokens: Option<u64>) -> Result<()> {
+/// Prints the separator and returns the prompt as plain and styled text. `terminal`
+/// adds the shell's directory and window title when they are known.
+pub fn repl_prompt(
+    skin: &MadSkin,
+    last_response_total_tokens: Option<u64>,
+    terminal: Option<&TerminalSnapshot>,
+) -> (String, String) {
     let (width, _) = terminal_size();
     let separator = "─".repeat(usize::from(width.max(1)));
     let timestamp = current_timestamp_hms();
     let token_count = last_response_total_tokens
         .map(|tokens| tokens.to_string())
         .unwrap_or_else(|| "n/a".to_string());
-    let prompt = format!("*{timestamp}* **{token_count}** ❯ ");
+    let mut location = String::new();
+    if let Some(cwd) = terminal.and_then(|terminal| terminal.cwd.as_deref()) {
+        location.push_str(&format!(" {cwd}"));
+    }
+    if let Some(title) = terminal.and_then(|terminal| terminal.title.as_deref()) {
+        location.push_str(&format!(" ({title})"));
+    }
+    // The location stays out of the markdown so paths and titles print as they are.
+    let styled = format!("*{timestamp}* **{token_count}**");
 
     println!("{}", skin.inline(&separator));
-    print!("{}", skin.inline(&prompt));
-    io::stdout().flush().context("failed to flush repl prompt")
+    (
+        format!("{timestamp} {token_count}{location} ❯ "),
+        format!("{}{location} ❯ ", skin.inline(&styled)),
+    )
 }
 
-fn print_agent_response(skin: &MadSkin, response: &str) {
+pub fn print_agent_response(skin: &MadSkin, response: &str) {
     skin.print_text(response);
 }
 
-fn resolve_skin(skin_mode: SkinMode) -> MadSkin {
+pub fn resolve_skin(skin_mode: SkinMode) -> MadSkin {
     match skin_mode {
         SkinMode::Light => MadSkin::default_light(),
         SkinMode::Dark => MadSkin::default_dark(),
@@ -278,23 +429,19 @@ mod skin_tests {
     }
 }
 
-async fn read_repl_line() -> Result<Option<String>> {
-    tokio::task::spawn_blocking(|| -> io::Result<Option<String>> {
-        let mut line = String::new();
-        match io::stdin().read_line(&mut line) {
-            Ok(0) => Ok(None),
-            Ok(_) => Ok(Some(line)),
-            Err(err) => Err(err),
-        }
-    })
-    .await
-    .context("failed to join repl input reader")?
-    .context("failed to read repl line")
-}
-
 #[cfg(test)]
 mod tests {
-    use super::{PrefixedCommand, current_timestamp_hms, parse_prefixed_command};
+    use super::{
+        PrefixedCommand, ReplOptions, current_timestamp_hms, parse_prefixed_command,
+        run_batch_prompt, run_single_command,
+    };
+    use crate::agent::AgentRuntime;
+    use crate::batch::{BatchStatus, OutputFormat};
+    use crate::config::SkinMode;
+    use crate::session_capture::SessionCapture;
+    use crate::terminal_session::TerminalSession;
+    use crate::test_support::{FakeLlmServer, TEST_API_KEY, shell_session_config, wait_for_prompt};
+    use serde_json::{Value, json};
 
     #[test]
     fn parses_raw_with_tab_separated_payload() {
@@ -302,6 +449,14 @@ mod tests {
         assert_eq!(parsed, Some(PrefixedCommand::Raw("\\x03".to_string())));
     }
 
+    #[test]
+    fn parses_signal_names() {
+        assert_eq!(
+            parse_prefixed_command(":signal INT"),
+            Some(PrefixedCommand::Signal("INT".to_string()))
+        );
+    }
+
     #[test]
     fn rejects_non_separated_prefix() {
         assert_eq!(parse_prefixed_command(":run echo hi"), None);
@@ -323,4 +478,152 @@ mod tests {
         assert_eq!(ts.as_bytes()[2], b':');
         assert_eq!(ts.as_bytes()[5], b':');
     }
+
+    #[tokio::test]
+    async fn single_command_drives_pty_and_records_capture() {
+        let server = FakeLlmServer::start();
+        server.push_tool_call(
+            "raw_input",
+            json!({"str": "echo from-repl\\r", "float": 0.3}),
+        );
+        server.push_text("Echoed **from-repl**.");
+
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        let capture = SessionCapture::new();
+        let result = run_single_command(
+            &session,
+            ReplOptions {
+                wait_ms: 100,
+                initial_prompt: "Use the raw_input tool.",
+                skin_mode: SkinMode::Default,
+                verbose: 0,
+                api_key: TEST_API_KEY,
+                base_url: Some(server.base_url()),
+                yolo: true,
+                redactor: None,
+                max_turns: None,
+            },
+            "echo something",
+            Some(capture.clone()),
+            OutputFormat::Text,
+        )
+        .await;
+        session.shutdown().await.expect("shutdown session");
+        assert_eq!(result.expect("run single command"), BatchStatus::Success);
+
+        let events: Vec<Value> = capture
+            .render_jsonl()
+            .expect("render capture")
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("valid json line"))
+            .collect();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["text"], "echo something");
+        assert_eq!(events[1]["tool_name"], "raw_input");
+        assert!(
+            events[1]["snapshot"]
+                .as_str()
+                .expect("snapshot string")
+                .contains("\nfrom-repl\n")
+        );
+        assert_eq!(events[2]["markdown"], "Echoed **from-repl**.");
+    }
+
+    async fn batch_runtime(
+        server: &FakeLlmServer,
+        max_turns: usize,
+    ) -> (TerminalSession, AgentRuntime) {
+        let session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        let mut runtime = AgentRuntime::new(
+            session.handle(),
+            "Use the raw_input tool.",
+            TEST_API_KEY,
+            Some(server.base_url()),
+            true,
+            None,
+            None,
+        )
+        .expect("create runtime");
+        runtime.set_max_turns(max_turns);
+        (session, runtime)
+    }
+
+    #[tokio::test]
+    async fn batch_report_counts_tool_calls_and_captures_screen() {
+        let server = FakeLlmServer::start();
+        server.push_tool_call(
+            "raw_input",
+            json!({"str": "echo batch-ok\\r", "float": 0.3}),
+        );
+        server.push_text("done");
+        let (mut session, mut runtime) = batch_runtime(&server, 10).await;
+
+        let report = run_batch_prompt(&mut runtime, "echo", None).await;
+        session.shutdown().await.expect("shutdown session");
+
+        assert_eq!(report.status, BatchStatus::Success);
+        assert_eq!(report.response.as_deref(), Some("done"));
+        assert_eq!(report.tool_calls, 1);
+        assert_eq!(report.usage.total_tokens, 30);
+        assert!(report.final_screen.contains("\nbatch-ok\n"));
+    }
+
+    #[tokio::test]
+    async fn batch_report_flags_exhausted_turn_budget() {
+        let server = FakeLlmServer::start();
+        for _ in 0..3 {
+            server.push_tool_call("raw_input", json!({"str": "", "float": 0.0}));
+        }
+        let (mut session, mut runtime) = batch_runtime(&server, 1).await;
+
+        let report = run_batch_prompt(&mut runtime, "loop forever", None).await;
+        session.shutdown().await.expect("shutdown session");
+
+        assert_eq!(report.status, BatchStatus::BudgetExceeded);
+        assert_eq!(report.status.exit_code(), std::process::ExitCode::from(3));
+        assert!(report.error.is_some());
+        assert!(report.usage.total_tokens >= 30, "{:?}", report.usage);
+    }
+
+    #[tokio::test]
+    async fn batch_report_explains_a_missing_final_screen() {
+        let server = FakeLlmServer::start();
+        server.push_text("done");
+        let (mut session, mut runtime) = batch_runtime(&server, 10).await;
+        session.shutdown().await.expect("shutdown session");
+
+        let report = run_batch_prompt(&mut runtime, "echo", None).await;
+
+        assert_eq!(report.status, BatchStatus::AgentError);
+        assert_eq!(report.response.as_deref(), Some("done"));
+        let error = report.error.expect("error");
+        assert!(error.contains("final screen"), "{error}");
+        assert_eq!(report.final_screen, "");
+    }
+
+    #[tokio::test]
+    async fn batch_report_uses_finish_verdict() {
+        let server = FakeLlmServer::start();
+        server.push_tool_call(
+            "finish",
+            json!({"status": "failure", "summary": "The build is broken."}),
+        );
+        let (mut session, mut runtime) = batch_runtime(&server, 10).await;
+
+        let report = run_batch_prompt(&mut runtime, "build it", None).await;
+        session.shutdown().await.expect("shutdown session");
+
+        assert_eq!(report.status, BatchStatus::TaskFailed);
+        assert_eq!(report.response.as_deref(), Some("The build is broken."));
+        assert_eq!(report.tool_calls, 0);
+        let json = serde_json::to_value(&report).expect("serialize report");
+        assert_eq!(json["finish"]["status"], "failure");
+        assert_eq!(report.status.exit_code(), std::process::ExitCode::from(5));
+    }
 }
@@ -0,0 +1,272 @@
+use anyhow::{Context, Result, bail};
+use rig::OneOrMany;
+use rig::completion::{
+    AssistantContent, CompletionError, CompletionModel, CompletionRequest, CompletionResponse,
+    GetTokenUsage, Usage,
+};
+use rig::streaming::StreamingCompletionResponse;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::agent::{AgentRuntime, FINISH_TOOL};
+use crate::config::SessionConfig;
+use crate::session_capture::{SessionCapture, SessionEvent};
+use crate::terminal_session::TerminalSession;
+use crate::test_support::wait_for_prompt;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RecordedToolCall {
+    pub tool_name: String,
+    pub params: Value,
+    pub snapshot: String,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayTurn {
+    pub prompt: String,
+    pub tool_calls: Vec<RecordedToolCall>,
+    pub response: String,
+}
+
+pub fn parse_jsonl(contents: &str) -> Result<Vec<SessionEvent>> {
+    contents
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(idx, line)| {
+            serde_json::from_str(line)
+                .with_context(|| format!("invalid session event on line {}", idx + 1))
+        })
+        .collect()
+}
+
+pub fn script_from_events(events: Vec<SessionEvent>) -> Result<Vec<ReplayTurn>> {
+    let mut turns: Vec<ReplayTurn> = Vec::new();
+
+    for event in events {
+        match event {
+            SessionEvent::UserInput { text, .. } => {
+                if text.starts_with(':') {
+                    bail!("replay only supports agent prompts, found REPL command `{text}`");
+                }
+                turns.push(ReplayTurn {
+                    prompt: text,
+                    tool_calls: Vec::new(),
+                    response: String::new(),
+                });
+            }
+            SessionEvent::ToolCall {
+                tool_name,
+                params,
+                snapshot,
+                ..
+            } => {
+                let turn = turns
+                    .last_mut()
+                    .context("tool call recorded before any prompt")?;
+                turn.tool_calls.push(RecordedToolCall {
+                    tool_name,
+                    params,
+                    snapshot,
+                });
+            }
+            SessionEvent::AssistantResponse { markdown, .. } => {
+                let turn = turns
+                    .last_mut()
+                    .context("assistant response recorded before any prompt")?;
+                turn.response = markdown;
+            }
+            SessionEvent::TaskFinished {
+                status, summary, ..
+            } => {
+                let turn = turns
+                    .last_mut()
+                    .context("task result recorded before any prompt")?;
+                turn.tool_calls.push(RecordedToolCall {
+                    tool_name: FINISH_TOOL.to_string(),
+                    params: json!({ "status": status, "summary": summary }),
+                    snapshot: String::new(),
+                });
+            }
+            // The `command_output` tool call that produced it is replayed instead.
+            SessionEvent::CommandOutput { .. } => {}
+        }
+    }
+
+    Ok(turns)
+}
+
+/// Completion model that answers each request with the next scripted step.
+#[derive(Clone, Default)]
+pub struct ReplayModel {
+    steps: Arc<Mutex<VecDeque<AssistantContent>>>,
+}
+
+impl ReplayModel {
+    pub fn queue_turn(&self, turn: &ReplayTurn) {
+        let mut steps = self
+            .steps
+            .lock()
+            .expect("replay mutex should not be poisoned");
+        for (idx, call) in turn.tool_calls.iter().enumerate() {
+            steps.push_back(AssistantContent::tool_call(
+                format!("replay_call_{idx}"),
+                call.tool_name.clone(),
+                call.params.clone(),
+            ));
+        }
+        // `finish` ends the prompt, so the model is never asked for a closing text.
+        if !turn
+            .tool_calls
+            .iter()
+            .any(|call| call.tool_name == FINISH_TOOL)
+        {
+            steps.push_back(AssistantContent::text(turn.response.clone()));
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.steps
+            .lock()
+            .expect("replay mutex should not be poisoned")
+            .len()
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayStreamingResponse;
+
+impl GetTokenUsage for ReplayStreamingResponse {
+    fn token_usage(&self) -> Option<Usage> {
+        None
+    }
+}
+
+impl CompletionModel for ReplayModel {
+    type Response = ();
+    type StreamingResponse = ReplayStreamingResponse;
+    type Client = ();
+
+    fn make(_client: &Self::Client, _model: impl Into<String>) -> Self {
+        Self::default()
+    }
+
+    async fn completion(
+        &self,
+        _request: CompletionRequest,
+    ) -> Result<CompletionResponse<Self::Response>, CompletionError> {
+        let step = self
+            .steps
+            .lock()
+            .expect("replay mutex should not be poisoned")
+            .pop_front()
+            .ok_or_else(|| CompletionError::ProviderError("replay script exhausted".to_string()))?;
+
+        Ok(CompletionResponse {
+            choice: OneOrMany::one(step),
+            usage: Usage::new(),
+            raw_response: (),
+            message_id: None,
+        })
+    }
+
+    async fn stream(
+        &self,
+        _request: CompletionRequest,
+    ) -> Result<StreamingCompletionResponse<Self::StreamingResponse>, CompletionError> {
+        Err(CompletionError::ProviderError(
+            "the replay model does not support streaming".to_string(),
+        ))
+    }
+}
+
+/// Replays `turns` against a fresh shell and returns the tool calls it produced.
+pub async fn replay(options: SessionConfig, turns: &[ReplayTurn]) -> Result<Vec<RecordedToolCall>> {
+    let mut session = TerminalSession::start(options).await?;
+    wait_for_prompt(&session.handle()).await;
+    let capture = SessionCapture::new();
+    let model = ReplayModel::default();
+    let mut runtime = AgentRuntime::with_model(
+        model.clone(),
+        session.handle(),
+        "replay",
+        true,
+        Some(capture.clone()),
+        None,
+    );
+
+    let result = async {
+        for turn in turns {
+            model.queue_turn(turn);
+            capture.record_user_input(&turn.prompt);
+            let response = runtime.prompt(&turn.prompt).await?;
+            capture.record_assistant_response(&response.output);
+            if model.remaining() != 0 {
+                bail!(
+                    "agent stopped before consuming the replay turn for {:?}",
+                    turn.prompt
+                );
+            }
+        }
+        Ok(())
+    }
+    .await;
+    let shutdown = session.shutdown().await;
+    result?;
+    shutdown?;
+
+    let replayed = script_from_events(parse_jsonl(&capture.render_jsonl()?)?)?;
+    Ok(replayed
+        .into_iter()
+        .flat_map(|turn| turn.tool_calls)
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_jsonl, replay, script_from_events};
+    use crate::test_support::shell_session_config;
+
+    const ECHO_SESSION: &str = include_str!("../tests/fixtures/echo_session.jsonl");
+
+    #[test]
+    fn groups_events_into_turns() {
+        let turns = script_from_events(parse_jsonl(ECHO_SESSION).expect("parse fixture"))
+            .expect("build script");
+
+        assert_eq!(turns.len(), 1);
+        assert_eq!(turns[0].tool_calls.len(), 2);
+        assert_eq!(turns[0].tool_calls[0].tool_name, "raw_input");
+        assert!(!turns[0].response.is_empty());
+    }
+
+    #[test]
+    fn rejects_repl_commands_in_script() {
+        let events = parse_jsonl(r#"{"type":"user_input","timestamp":"t","text":":snap"}"#)
+            .expect("parse event");
+
+        assert!(script_from_events(events).is_err());
+    }
+
+    #[tokio::test]
+    async fn replays_echo_session_snapshots() {
+        let turns = script_from_events(parse_jsonl(ECHO_SESSION).expect("parse fixture"))
+            .expect("build script");
+        let replayed = replay(shell_session_config(), &turns)
+            .await
+            .expect("replay session");
+        let expected: Vec<_> = turns.into_iter().flat_map(|turn| turn.tool_calls).collect();
+
+        assert_eq!(replayed.len(), expected.len());
+        for (idx, (actual, expected)) in replayed.iter().zip(&expected).enumerate() {
+            assert_eq!(
+                actual.snapshot,
+                expected.snapshot,
+                "snapshot mismatch for tool call #{}",
+                idx + 1
+            );
+        }
+    }
+}
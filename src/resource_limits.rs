@@ -0,0 +1,451 @@
+use anyhow::{Context, Result, bail};
+use rustix::process::{Resource, Rlimit, getuid, setrlimit};
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::config::LimitsConfig;
+
+const CPU_PERIOD_USEC: u64 = 100_000;
+const MIB: u64 = 1024 * 1024;
+
+static CGROUP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// rlimits applied to the shell between fork and exec; every descendant inherits them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ShellRlimits {
+    limits: Vec<(Resource, Rlimit)>,
+}
+
+impl ShellRlimits {
+    pub fn from_config(config: &LimitsConfig) -> Self {
+        let mut limits = Vec::new();
+        if let Some(seconds) = config.cpu_seconds {
+            // The soft limit delivers SIGXCPU; the hard limit one second later is SIGKILL.
+            limits.push((Resource::Cpu, rlimit(seconds, seconds.saturating_add(1))));
+        }
+        if let Some(mb) = config.address_space_mb {
+            let bytes = mb.saturating_mul(MIB);
+            limits.push((Resource::As, rlimit(bytes, bytes)));
+        }
+        if let Some(files) = config.open_files {
+            limits.push((Resource::Nofile, rlimit(files, files)));
+        }
+        if let Some(processes) = config.processes {
+            limits.push((Resource::Nproc, rlimit(processes, processes)));
+        }
+        Self { limits }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.limits.is_empty()
+    }
+
+    pub fn apply(&self) -> io::Result<()> {
+        for (resource, limit) in &self.limits {
+            setrlimit(*resource, *limit)?;
+        }
+        Ok(())
+    }
+}
+
+fn rlimit(current: u64, maximum: u64) -> Rlimit {
+    Rlimit {
+        current: Some(current),
+        maximum: Some(maximum),
+    }
+}
+
+/// Describes a shell exit that was caused by one of the configured rlimits.
+pub fn describe_limit_exit(status: ExitStatus, config: &LimitsConfig) -> Option<String> {
+    match status.signal()? {
+        libc::SIGXCPU => Some(format!(
+            "The shell was killed by SIGXCPU after using its {} CPU seconds (limits.cpu_seconds); ask the user to :reset it.",
+            config.cpu_seconds.unwrap_or_default()
+        )),
+        // Memory cgroup kills are reported from memory.events instead.
+        libc::SIGKILL if config.cpu_seconds.is_some() => Some(format!(
+            "The shell was killed by SIGKILL, possibly for going past its {} CPU seconds (limits.cpu_seconds).",
+            config.cpu_seconds.unwrap_or_default()
+        )),
+        _ => None,
+    }
+}
+
+/// Describes a command that the shell reported exiting with a signal a configured limit
+/// sends, or that a failed allocation under `address_space_mb` usually ends in.
+/// `exit` is the status the shell saw, 128 plus the signal number for a signal.
+pub fn describe_command_exit(exit: i32, config: &LimitsConfig) -> Option<String> {
+    match exit.checked_sub(128).filter(|signal| *signal > 0)? {
+        libc::SIGXCPU if config.cpu_seconds.is_some() => Some(format!(
+            "A command was killed by SIGXCPU after using its {} CPU seconds (limits.cpu_seconds).",
+            config.cpu_seconds.unwrap_or_default()
+        )),
+        libc::SIGKILL if config.cpu_seconds.is_some() => Some(format!(
+            "A command was killed by SIGKILL, possibly for going past its {} CPU seconds (limits.cpu_seconds).",
+            config.cpu_seconds.unwrap_or_default()
+        )),
+        signal @ (libc::SIGABRT | libc::SIGSEGV) if config.address_space_mb.is_some() => {
+            Some(format!(
+                "A command died of {}; with limits.address_space_mb at {} MiB, that is often an allocation that failed.",
+                if signal == libc::SIGABRT {
+                    "SIGABRT"
+                } else {
+                    "SIGSEGV"
+                },
+                config.address_space_mb.unwrap_or_default()
+            ))
+        }
+        _ => None,
+    }
+}
+
+/// With `limits.processes` set, notices when the user runs that many processes (or threads),
+/// which is when forks start to fail. Each run-in is reported once.
+#[derive(Debug, Default)]
+pub struct ProcessLimit {
+    at_limit: bool,
+}
+
+impl ProcessLimit {
+    /// `exits` are the statuses of the commands that finished since the last call. A failed
+    /// fork fails a command, so `/proc` is only scanned after one of them failed.
+    pub fn check(&mut self, exits: &[i32], config: &LimitsConfig) -> Option<String> {
+        let limit = config.processes?;
+        if exits.iter().all(|&exit| exit == 0) {
+            return None;
+        }
+        let tasks = count_user_tasks(getuid().as_raw())?;
+        let was_at_limit = std::mem::replace(&mut self.at_limit, tasks >= limit);
+        (self.at_limit && !was_at_limit).then(|| {
+            format!(
+                "All {limit} processes allowed by limits.processes are in use, so starting another fails (fork: Resource temporarily unavailable)."
+            )
+        })
+    }
+}
+
+/// RLIMIT_NPROC counts every thread whose real uid is `uid`.
+fn count_user_tasks(uid: u32) -> Option<u64> {
+    let mut tasks = 0;
+    for entry in fs::read_dir("/proc").ok()?.flatten() {
+        let Ok(status) = fs::read_to_string(entry.path().join("status")) else {
+            continue;
+        };
+        if let Some(threads) = parse_user_threads(&status, uid) {
+            tasks += threads;
+        }
+    }
+    Some(tasks)
+}
+
+/// The `Threads:` count from a `/proc/PID/status`, when its real uid is `uid`.
+fn parse_user_threads(status: &str, uid: u32) -> Option<u64> {
+    let field = |name: &str| {
+        status
+            .lines()
+            .find_map(|line| line.strip_prefix(name))?
+            .split_whitespace()
+            .next()?
+            .parse::<u64>()
+            .ok()
+    };
+    (field("Uid:")? == u64::from(uid))
+        .then(|| field("Threads:"))
+        .flatten()
+}
+
+/// A cgroup v2 leaf holding one shell and its descendants, removed on drop.
+pub struct ShellCgroup {
+    path: PathBuf,
+    procs: File,
+    memory_max_mb: Option<u64>,
+    oom_kills_seen: u64,
+}
+
+impl ShellCgroup {
+    pub fn create(config: &LimitsConfig) -> Result<Option<Self>> {
+        if config.memory_max_mb.is_none() && config.cpu_max_percent.is_none() {
+            return Ok(None);
+        }
+
+        let parent = config
+            .cgroup_parent
+            .clone()
+            .context("limits.memory_max_mb and limits.cpu_max_percent need limits.cgroup_parent")?;
+        enable_controllers(&parent, config).with_context(|| {
+            format!(
+                "failed to enable cgroup controllers in {} (set limits.cgroup_parent to a delegated cgroup)",
+                parent.display()
+            )
+        })?;
+
+        let name = format!(
+            "gibberish-{}-{}",
+            std::process::id(),
+            CGROUP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        );
+        let path = parent.join(name);
+        fs::create_dir(&path)
+            .with_context(|| format!("failed to create cgroup {}", path.display()))?;
+
+        let setup = (|| -> Result<File> {
+            if let Some(mb) = config.memory_max_mb {
+                write_cgroup_file(&path, "memory.max", &mb.saturating_mul(MIB).to_string())?;
+                // Without this the kernel swaps instead of killing, and the machine still crawls.
+                let _ = write_cgroup_file(&path, "memory.swap.max", "0");
+            }
+            if let Some(percent) = config.cpu_max_percent {
+                write_cgroup_file(&path, "cpu.max", &cpu_max(percent)?)?;
+            }
+            OpenOptions::new()
+                .write(true)
+                .open(path.join("cgroup.procs"))
+                .context("failed to open cgroup.procs")
+        })();
+        let procs = match setup {
+            Ok(procs) => procs,
+            Err(err) => {
+                let _ = fs::remove_dir(&path);
+                return Err(err);
+            }
+        };
+
+        Ok(Some(Self {
+            path,
+            procs,
+            memory_max_mb: config.memory_max_mb,
+            oom_kills_seen: 0,
+        }))
+    }
+
+    /// Returns a handle on `cgroup.procs` that a forked child can use to join the cgroup.
+    pub fn entry(&self) -> Result<CgroupEntry> {
+        let procs = self
+            .procs
+            .try_clone()
+            .context("failed to duplicate cgroup.procs")?;
+        Ok(CgroupEntry { procs })
+    }
+
+    /// Returns a notice for OOM kills that happened since the last call.
+    pub fn take_notice(&mut self) -> Option<String> {
+        let events = fs::read_to_string(self.path.join("memory.events")).ok()?;
+        let total = parse_oom_kills(&events)?;
+        let new_kills = total.checked_sub(self.oom_kills_seen).filter(|n| *n > 0)?;
+        self.oom_kills_seen = total;
+        Some(format!(
+            "The memory limit ({} MiB, limits.memory_max_mb) killed {new_kills} process(es) since the last snapshot.",
+            self.memory_max_mb.unwrap_or_default()
+        ))
+    }
+}
+
+pub struct CgroupEntry {
+    procs: File,
+}
+
+impl CgroupEntry {
+    /// Moves the calling process into the cgroup; meant to run in `pre_exec`.
+    pub fn enter(&self) -> io::Result<()> {
+        rustix::io::write(&self.procs, b"0")?;
+        Ok(())
+    }
+}
+
+impl Drop for ShellCgroup {
+    fn drop(&mut self) {
+        let _ = write_cgroup_file(&self.path, "cgroup.kill", "1");
+        for _ in 0..50 {
+            if fs::remove_dir(&self.path).is_ok() {
+                return;
+            }
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+    }
+}
+
+fn enable_controllers(parent: &Path, config: &LimitsConfig) -> Result<()> {
+    let enabled = fs::read_to_string(parent.join("cgroup.subtree_control"))
+        .context("failed to read cgroup.subtree_control")?;
+    let mut wanted = Vec::new();
+    if config.memory_max_mb.is_some() {
+        wanted.push("memory");
+    }
+    if config.cpu_max_percent.is_some() {
+        wanted.push("cpu");
+    }
+    for controller in wanted {
+        if !enabled.split_whitespace().any(|name| name == controller) {
+            write_cgroup_file(parent, "cgroup.subtree_control", &format!("+{controller}"))?;
+        }
+    }
+    Ok(())
+}
+
+fn write_cgroup_file(dir: &Path, name: &str, value: &str) -> Result<()> {
+    fs::write(dir.join(name), value)
+        .with_context(|| format!("failed to write {value:?} to {}", dir.join(name).display()))
+}
+
+fn cpu_max(percent: u32) -> Result<String> {
+    if percent == 0 {
+        bail!("limits.cpu_max_percent must be greater than zero");
+    }
+    let quota = CPU_PERIOD_USEC * u64::from(percent) / 100;
+    Ok(format!("{quota} {CPU_PERIOD_USEC}"))
+}
+
+fn parse_oom_kills(events: &str) -> Option<u64> {
+    events.lines().find_map(|line| {
+        let (key, value) = line.split_once(' ')?;
+        (key == "oom_kill").then(|| value.trim().parse().ok())?
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ProcessLimit, ShellRlimits, cpu_max, describe_command_exit, describe_limit_exit,
+        parse_oom_kills, parse_user_threads,
+    };
+    use crate::config::LimitsConfig;
+    use crate::terminal_session::TerminalSession;
+    use crate::test_support::{shell_session_config, wait_for_prompt};
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+    use std::time::Duration;
+
+    #[test]
+    fn formats_cpu_max_as_quota_and_period() {
+        assert_eq!(cpu_max(50).expect("cpu.max"), "50000 100000");
+        assert_eq!(cpu_max(200).expect("cpu.max"), "200000 100000");
+        assert!(cpu_max(0).is_err());
+    }
+
+    #[test]
+    fn reads_oom_kill_counter() {
+        let events = "low 0\nhigh 0\nmax 3\noom 2\noom_kill 2\noom_group_kill 0\n";
+
+        assert_eq!(parse_oom_kills(events), Some(2));
+        assert_eq!(parse_oom_kills("low 0\n"), None);
+    }
+
+    #[test]
+    fn explains_sigxcpu_exit() {
+        let config = LimitsConfig {
+            cpu_seconds: Some(5),
+            ..LimitsConfig::default()
+        };
+
+        let notice = describe_limit_exit(ExitStatus::from_raw(libc::SIGXCPU), &config)
+            .expect("notice for SIGXCPU");
+        assert!(notice.contains("5 CPU seconds"));
+        assert_eq!(describe_limit_exit(ExitStatus::from_raw(0), &config), None);
+        assert!(ShellRlimits::from_config(&LimitsConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn explains_commands_killed_by_limits() {
+        let config = LimitsConfig {
+            cpu_seconds: Some(5),
+            address_space_mb: Some(512),
+            ..LimitsConfig::default()
+        };
+
+        let sigxcpu = describe_command_exit(128 + libc::SIGXCPU, &config).expect("SIGXCPU");
+        assert!(sigxcpu.contains("5 CPU seconds"), "{sigxcpu}");
+        let sigabrt = describe_command_exit(128 + libc::SIGABRT, &config).expect("SIGABRT");
+        assert!(sigabrt.contains("512 MiB"), "{sigabrt}");
+        assert_eq!(describe_command_exit(1, &config), None);
+        let memory_only = LimitsConfig {
+            memory_max_mb: Some(512),
+            ..LimitsConfig::default()
+        };
+        assert_eq!(
+            describe_command_exit(128 + libc::SIGKILL, &memory_only),
+            None
+        );
+        assert_eq!(
+            describe_limit_exit(ExitStatus::from_raw(libc::SIGKILL), &memory_only),
+            None
+        );
+        assert_eq!(
+            describe_command_exit(128 + libc::SIGXCPU, &LimitsConfig::default()),
+            None
+        );
+
+        let status = "Name:\tmake\nUid:\t1000\t1000\t1000\t1000\nThreads:\t3\n";
+        assert_eq!(parse_user_threads(status, 1000), Some(3));
+        assert_eq!(parse_user_threads(status, 0), None);
+    }
+
+    #[test]
+    fn reports_the_process_limit_once_after_a_failed_command() {
+        // This test process alone uses up a limit of one.
+        let config = LimitsConfig {
+            processes: Some(1),
+            ..LimitsConfig::default()
+        };
+        let mut process_limit = ProcessLimit::default();
+
+        assert_eq!(process_limit.check(&[0], &config), None);
+        let notice = process_limit.check(&[0, 1], &config).expect("notice");
+        assert!(notice.contains("All 1 processes"), "{notice}");
+        assert_eq!(process_limit.check(&[1], &config), None);
+        assert_eq!(
+            ProcessLimit::default().check(&[1], &LimitsConfig::default()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn reports_commands_killed_by_sigxcpu() {
+        let mut options = shell_session_config();
+        options.shell.integration = true;
+        options.limits.cpu_seconds = Some(30);
+
+        let mut session = TerminalSession::start(options)
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+        handle
+            .send_input("sh -c 'kill -XCPU $$'\r")
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let notices = handle.take_limit_notices().await.expect("notices");
+        let again = handle.take_limit_notices().await.expect("notices");
+        session.shutdown().await.expect("shutdown session");
+
+        assert_eq!(notices.len(), 1, "{notices:?}");
+        assert!(notices[0].contains("SIGXCPU"), "{notices:?}");
+        assert!(again.is_empty(), "{again:?}");
+    }
+
+    #[tokio::test]
+    async fn shell_inherits_configured_rlimits() {
+        let mut options = shell_session_config();
+        options.limits.open_files = Some(64);
+        options.limits.cpu_seconds = Some(30);
+
+        let mut session = TerminalSession::start(options)
+            .await
+            .expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        session
+            .handle()
+            .send_input("echo n=$(ulimit -n) t=$(ulimit -t)\r")
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let snapshot = session.snapshot().await.expect("snapshot");
+        session.shutdown().await.expect("shutdown session");
+
+        assert!(snapshot.render().contains("n=64 t=30"));
+    }
+}
@@ -0,0 +1,406 @@
+use anyhow::{Context, Result, bail, ensure};
+use rustix::fs::statvfs;
+use rustix::mount::{
+    MountFlags, MountPropagationFlags, UnmountFlags, mount, mount_bind, mount_bind_recursive,
+    mount_change, mount_remount, unmount,
+};
+use rustix::process::{Pid, Signal, WaitOptions, chdir, getgid, getuid, pivot_root, waitpid};
+use rustix::thread::{UnshareFlags, unshare_unsafe};
+use serde::{Deserialize, Serialize};
+use std::convert::Infallible;
+use std::ffi::OsString;
+use std::fs;
+use std::os::unix::process::CommandExt;
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use crate::config::SandboxConfig;
+
+pub const SANDBOX_INIT_ARG: &str = "--sandbox-init";
+const SANDBOX_PLAN_ENV: &str = "GIBBERISH_SANDBOX_PLAN";
+const STAGING_DIR: &str = "/tmp";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SandboxPlan {
+    pub workdir: PathBuf,
+    pub read_only: Vec<PathBuf>,
+    pub network: bool,
+    #[serde(default)]
+    pub arg0: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum SandboxMount {
+    Tmpfs(PathBuf),
+    Proc(PathBuf),
+    Dev(PathBuf),
+    Bind { path: PathBuf, writable: bool },
+}
+
+impl SandboxMount {
+    fn path(&self) -> &Path {
+        match self {
+            Self::Tmpfs(path) | Self::Proc(path) | Self::Dev(path) => path,
+            Self::Bind { path, .. } => path,
+        }
+    }
+}
+
+impl SandboxPlan {
+    pub fn from_config(config: &SandboxConfig, shell_cwd: &Path) -> Result<Option<Self>> {
+        if !config.enabled {
+            return Ok(None);
+        }
+
+        let workdir = match config.workdir.as_ref() {
+            Some(workdir) => shell_cwd.join(workdir),
+            None => shell_cwd.to_path_buf(),
+        };
+        let workdir = workdir
+            .canonicalize()
+            .with_context(|| format!("sandbox.workdir {} does not exist", workdir.display()))?;
+        ensure!(
+            workdir.is_dir(),
+            "sandbox.workdir {} is not a directory",
+            workdir.display()
+        );
+
+        for path in &config.read_only {
+            ensure!(
+                path.is_absolute(),
+                "sandbox.read_only entries must be absolute paths (got {})",
+                path.display()
+            );
+        }
+
+        Ok(Some(Self {
+            workdir,
+            read_only: config.read_only.clone(),
+            network: config.network,
+            arg0: None,
+        }))
+    }
+
+    /// Builds the argv that re-executes gibberish as the sandbox init process,
+    /// which then execs `program` inside the namespaces.
+    pub fn wrap_command(&self, program: &str, args: &[String]) -> Result<(PathBuf, Vec<OsString>)> {
+        let exe = std::env::current_exe().context("failed to locate gibberish executable")?;
+        let mut argv = vec![OsString::from(SANDBOX_INIT_ARG), OsString::from("--")];
+        argv.push(OsString::from(program));
+        argv.extend(args.iter().map(OsString::from));
+        Ok((exe, argv))
+    }
+
+    pub fn env(&self) -> Result<(&'static str, String)> {
+        let plan = serde_json::to_string(self).context("failed to serialize sandbox plan")?;
+        Ok((SANDBOX_PLAN_ENV, plan))
+    }
+
+    fn mounts(&self) -> Vec<SandboxMount> {
+        let mut mounts = vec![
+            SandboxMount::Tmpfs(PathBuf::from("/tmp")),
+            SandboxMount::Proc(PathBuf::from("/proc")),
+            SandboxMount::Dev(PathBuf::from("/dev")),
+        ];
+        mounts.extend(self.read_only.iter().map(|path| SandboxMount::Bind {
+            path: path.clone(),
+            writable: false,
+        }));
+        mounts.push(SandboxMount::Bind {
+            path: self.workdir.clone(),
+            writable: true,
+        });
+        // Parents must be mounted before anything nested inside them.
+        mounts.sort_by_key(|mount| mount.path().components().count());
+        mounts
+    }
+}
+
+/// Entry point for `gibberish --sandbox-init -- PROGRAM [ARGS...]`.
+///
+/// Runs before the async runtime starts so the process is still single-threaded
+/// when it unshares namespaces and forks.
+pub fn run_init(command: Vec<OsString>) -> ! {
+    let err = match init(command) {
+        Ok(never) => match never {},
+        Err(err) => err,
+    };
+    eprintln!("gibberish sandbox: {err:#}");
+    std::process::exit(127)
+}
+
+fn init(command: Vec<OsString>) -> Result<Infallible> {
+    let mut command = command.into_iter().skip_while(|arg| arg == "--");
+    let program = command
+        .next()
+        .context("missing program to run in sandbox")?;
+    let args: Vec<OsString> = command.collect();
+
+    let plan = std::env::var(SANDBOX_PLAN_ENV).context("missing sandbox plan")?;
+    let plan: SandboxPlan = serde_json::from_str(&plan).context("invalid sandbox plan")?;
+
+    let uid = getuid().as_raw();
+    let gid = getgid().as_raw();
+    let mut flags = UnshareFlags::NEWUSER | UnshareFlags::NEWNS | UnshareFlags::NEWPID;
+    if !plan.network {
+        flags |= UnshareFlags::NEWNET;
+    }
+    // SAFETY: we do not unshare the file descriptor table.
+    unsafe { unshare_unsafe(flags) }.context("failed to create sandbox namespaces")?;
+    fs::write("/proc/self/setgroups", "deny").context("failed to write setgroups")?;
+    fs::write("/proc/self/uid_map", format!("{uid} {uid} 1")).context("failed to map uid")?;
+    fs::write("/proc/self/gid_map", format!("{gid} {gid} 1")).context("failed to map gid")?;
+
+    // The first child of a new PID namespace becomes its PID 1, so fork once
+    // and keep this process around to mirror the shell's exit status.
+    // SAFETY: the process is single-threaded at this point.
+    match unsafe { libc::fork() } {
+        -1 => Err(std::io::Error::last_os_error()).context("failed to fork sandbox init"),
+        0 => {
+            rustix::process::set_parent_process_death_signal(Some(Signal::KILL))
+                .context("failed to set parent death signal")?;
+            enter_filesystem(&plan)?;
+            if !plan.network {
+                bring_up_loopback().context("failed to bring up loopback interface")?;
+            }
+            let mut command = Command::new(&program);
+            if let Some(arg0) = plan.arg0.as_ref() {
+                command.arg0(arg0);
+            }
+            let err = command.args(&args).env_remove(SANDBOX_PLAN_ENV).exec();
+            Err(err).with_context(|| format!("failed to exec {}", program.to_string_lossy()))
+        }
+        child => {
+            let child = Pid::from_raw(child).context("fork returned an invalid pid")?;
+            ignore_terminal_signals();
+            std::process::exit(wait_for_exit_code(child))
+        }
+    }
+}
+
+fn enter_filesystem(plan: &SandboxPlan) -> Result<()> {
+    mount_change(
+        "/",
+        MountPropagationFlags::DOWNSTREAM | MountPropagationFlags::REC,
+    )
+    .context("failed to stop mount propagation to the host")?;
+
+    let staging = Path::new(STAGING_DIR);
+    mount(
+        "tmpfs",
+        staging,
+        "tmpfs",
+        MountFlags::NODEV | MountFlags::NOSUID,
+        None,
+    )
+    .context("failed to mount sandbox staging tmpfs")?;
+    fs::create_dir(staging.join("newroot"))?;
+    fs::create_dir(staging.join("oldroot"))?;
+    pivot_root(staging, staging.join("oldroot")).context("failed to pivot to staging root")?;
+    chdir("/")?;
+    mount_bind("/newroot", "/newroot").context("failed to bind sandbox root")?;
+
+    for entry in plan.mounts() {
+        mount_entry(&entry)
+            .with_context(|| format!("failed to mount {} in sandbox", entry.path().display()))?;
+    }
+
+    chdir("/newroot")?;
+    pivot_root(".", ".").context("failed to pivot into sandbox root")?;
+    unmount(".", UnmountFlags::DETACH).context("failed to detach host root")?;
+    chdir(&plan.workdir)
+        .with_context(|| format!("failed to enter sandbox workdir {}", plan.workdir.display()))?;
+    Ok(())
+}
+
+fn mount_entry(entry: &SandboxMount) -> Result<()> {
+    let target = new_root_path(entry.path());
+    match entry {
+        SandboxMount::Tmpfs(_) => {
+            fs::create_dir_all(&target)?;
+            mount(
+                "tmpfs",
+                &target,
+                "tmpfs",
+                MountFlags::NODEV | MountFlags::NOSUID,
+                None,
+            )?;
+        }
+        SandboxMount::Proc(_) => {
+            fs::create_dir_all(&target)?;
+            mount(
+                "proc",
+                &target,
+                "proc",
+                MountFlags::NODEV | MountFlags::NOSUID | MountFlags::NOEXEC,
+                None,
+            )?;
+        }
+        SandboxMount::Dev(path) => {
+            fs::create_dir_all(&target)?;
+            mount_bind_recursive(old_root_path(path), &target)?;
+        }
+        SandboxMount::Bind { path, writable } => {
+            let source = old_root_path(path);
+            let Ok(metadata) = fs::symlink_metadata(&source) else {
+                // Missing read-only paths are skipped so one default list works across distros.
+                if *writable {
+                    bail!("{} does not exist", path.display());
+                }
+                return Ok(());
+            };
+
+            if metadata.file_type().is_symlink() {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                std::os::unix::fs::symlink(fs::read_link(&source)?, &target)?;
+                return Ok(());
+            }
+
+            if metadata.is_dir() {
+                fs::create_dir_all(&target)?;
+            } else {
+                if let Some(parent) = target.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                fs::write(&target, b"")?;
+            }
+            mount_bind(&source, &target)?;
+            if !writable {
+                // Remounting inside a user namespace must keep the host's locked flags.
+                let locked = MountFlags::from_bits_truncate(statvfs(&target)?.f_flag.bits() as u32)
+                    & (MountFlags::NOSUID
+                        | MountFlags::NODEV
+                        | MountFlags::NOEXEC
+                        | MountFlags::NOATIME
+                        | MountFlags::NODIRATIME
+                        | MountFlags::RELATIME);
+                mount_remount(&target, MountFlags::BIND | MountFlags::RDONLY | locked, "")?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn new_root_path(path: &Path) -> PathBuf {
+    Path::new("/newroot").join(relative_to_root(path))
+}
+
+fn old_root_path(path: &Path) -> PathBuf {
+    Path::new("/oldroot").join(relative_to_root(path))
+}
+
+fn relative_to_root(path: &Path) -> PathBuf {
+    path.components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .collect()
+}
+
+fn bring_up_loopback() -> std::io::Result<()> {
+    // SAFETY: plain socket/ioctl calls on a zeroed `ifreq` we own.
+    unsafe {
+        let fd = libc::socket(libc::AF_INET, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, 0);
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+        let mut ifr: libc::ifreq = std::mem::zeroed();
+        for (dst, src) in ifr.ifr_name.iter_mut().zip(b"lo\0") {
+            *dst = *src as libc::c_char;
+        }
+        let mut result = libc::ioctl(fd, libc::SIOCGIFFLAGS, &mut ifr);
+        if result == 0 {
+            ifr.ifr_ifru.ifru_flags |= libc::IFF_UP as libc::c_short;
+            result = libc::ioctl(fd, libc::SIOCSIFFLAGS, &ifr);
+        }
+        let err = std::io::Error::last_os_error();
+        libc::close(fd);
+        if result < 0 { Err(err) } else { Ok(()) }
+    }
+}
+
+fn ignore_terminal_signals() {
+    // The init process shares the shell's terminal; keyboard signals are for the shell.
+    for signal in [
+        libc::SIGINT,
+        libc::SIGQUIT,
+        libc::SIGTSTP,
+        libc::SIGTTIN,
+        libc::SIGTTOU,
+    ] {
+        // SAFETY: installing SIG_IGN has no preconditions.
+        unsafe {
+            libc::signal(signal, libc::SIG_IGN);
+        }
+    }
+}
+
+fn wait_for_exit_code(child: Pid) -> i32 {
+    loop {
+        match waitpid(Some(child), WaitOptions::empty()) {
+            Ok(Some((_, status))) => {
+                if let Some(code) = status.exit_status() {
+                    return code;
+                }
+                if let Some(signal) = status.terminating_signal() {
+                    return 128 + signal;
+                }
+            }
+            Ok(None) | Err(rustix::io::Errno::INTR) => {}
+            Err(_) => return 127,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SandboxMount, SandboxPlan, relative_to_root};
+    use crate::config::SandboxConfig;
+    use std::path::{Path, PathBuf};
+
+    #[test]
+    fn disabled_sandbox_has_no_plan() {
+        let plan =
+            SandboxPlan::from_config(&SandboxConfig::default(), Path::new("/")).expect("plan");
+
+        assert_eq!(plan, None);
+    }
+
+    #[test]
+    fn rejects_relative_read_only_paths() {
+        let config = SandboxConfig {
+            enabled: true,
+            workdir: Some(std::env::temp_dir()),
+            read_only: vec![PathBuf::from("usr")],
+            network: true,
+        };
+
+        assert!(SandboxPlan::from_config(&config, Path::new("/")).is_err());
+    }
+
+    #[test]
+    fn mounts_parents_before_nested_paths() {
+        let plan = SandboxPlan {
+            workdir: PathBuf::from("/tmp/project"),
+            read_only: vec![PathBuf::from("/usr"), PathBuf::from("/")],
+            network: false,
+            arg0: None,
+        };
+
+        let mounts = plan.mounts();
+        let paths: Vec<&Path> = mounts.iter().map(SandboxMount::path).collect();
+        assert_eq!(paths[0], Path::new("/"));
+        assert_eq!(paths.last().copied(), Some(Path::new("/tmp/project")));
+        let tmp = paths.iter().position(|path| *path == Path::new("/tmp"));
+        assert!(tmp < Some(paths.len() - 1));
+    }
+
+    #[test]
+    fn strips_root_and_parent_components() {
+        assert_eq!(
+            relative_to_root(Path::new("/usr/../lib")),
+            PathBuf::from("usr/lib")
+        );
+        assert_eq!(relative_to_root(Path::new("/")), PathBuf::new());
+    }
+}
@@ -1,12 +1,16 @@
 use anyhow::{Context, Result};
 use markdown::to_html;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
 use std::fmt::Write as _;
 use std::fs;
 use std::path::Path;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use time::OffsetDateTime;
 
+use crate::agent::{TaskResult, TaskStatus};
+
 #[derive(Clone)]
 pub struct SessionCapture {
     inner: Arc<Mutex<SessionCaptureInner>>,
@@ -15,10 +19,14 @@ pub struct SessionCapture {
 struct SessionCaptureInner {
     started_at: String,
     events: Vec<SessionEvent>,
+    listeners: Vec<EventListener>,
 }
 
-#[derive(Clone)]
-enum SessionEvent {
+type EventListener = Box<dyn Fn(&SessionEvent) + Send>;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum SessionEvent {
     UserInput {
         timestamp: String,
         text: String,
@@ -26,13 +34,30 @@ enum SessionEvent {
     ToolCall {
         timestamp: String,
         tool_name: String,
-        params_json: String,
+        params: Value,
         snapshot: String,
+        /// The shell's directory and the window title after the call, when reported.
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cwd: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
     },
     AssistantResponse {
         timestamp: String,
         markdown: String,
     },
+    TaskFinished {
+        timestamp: String,
+        status: TaskStatus,
+        summary: String,
+    },
+    /// Everything a command printed, kept the first time the agent reads it.
+    CommandOutput {
+        timestamp: String,
+        exit_status: Option<i32>,
+        duration_seconds: f64,
+        output: String,
+    },
 }
 
 impl SessionCapture {
@@ -41,6 +66,7 @@ impl SessionCapture {
             inner: Arc::new(Mutex::new(SessionCaptureInner {
                 started_at: now_timestamp(),
                 events: Vec::new(),
+                listeners: Vec::new(),
             })),
         }
     }
@@ -52,17 +78,26 @@ impl SessionCapture {
         });
     }
 
-    pub fn record_tool_call<T: Serialize>(&self, tool_name: &str, params: &T, snapshot: &str) {
-        let params_json = match serde_json::to_string_pretty(params) {
-            Ok(json) => json,
-            Err(err) => format!("{{\n  \"serialization_error\": {:?}\n}}", err.to_string()),
+    pub fn record_tool_call<T: Serialize>(
+        &self,
+        tool_name: &str,
+        params: &T,
+        snapshot: &str,
+        cwd: Option<&str>,
+        title: Option<&str>,
+    ) {
+        let params = match serde_json::to_value(params) {
+            Ok(value) => value,
+            Err(err) => json!({ "serialization_error": err.to_string() }),
         };
 
         self.push_event(SessionEvent::ToolCall {
             timestamp: now_timestamp(),
             tool_name: tool_name.to_string(),
-            params_json,
+            params,
             snapshot: snapshot.to_string(),
+            cwd: cwd.map(str::to_string),
+            title: title.map(str::to_string),
         });
     }
 
@@ -73,19 +108,76 @@ impl SessionCapture {
         });
     }
 
+    pub fn record_task_finished(&self, result: &TaskResult) {
+        self.push_event(SessionEvent::TaskFinished {
+            timestamp: now_timestamp(),
+            status: result.status,
+            summary: result.summary.clone(),
+        });
+    }
+
+    pub fn record_command_output(
+        &self,
+        exit_status: Option<i32>,
+        duration: Duration,
+        output: &str,
+    ) {
+        self.push_event(SessionEvent::CommandOutput {
+            timestamp: now_timestamp(),
+            exit_status,
+            duration_seconds: duration.as_secs_f64(),
+            output: output.to_string(),
+        });
+    }
+
     pub fn write_html(&self, path: &Path) -> Result<()> {
         let html = self.render_html();
         fs::write(path, html)
             .with_context(|| format!("failed to write session HTML to {}", path.display()))
     }
 
+    pub fn write_jsonl(&self, path: &Path) -> Result<()> {
+        let jsonl = self.render_jsonl()?;
+        fs::write(path, jsonl)
+            .with_context(|| format!("failed to write session JSONL to {}", path.display()))
+    }
+
+    pub fn render_jsonl(&self) -> Result<String> {
+        let events = self.events();
+        let mut out = String::new();
+        for event in &events {
+            let line = serde_json::to_string(event).context("failed to serialize session event")?;
+            out.push_str(&line);
+            out.push('\n');
+        }
+        Ok(out)
+    }
+
+    /// Calls `listener` with every event recorded from now on, in order.
+    pub fn on_event(&self, listener: impl Fn(&SessionEvent) + Send + 'static) {
+        if let Ok(mut inner) = self.inner.lock() {
+            inner.listeners.push(Box::new(listener));
+        }
+    }
+
+    fn events(&self) -> Vec<SessionEvent> {
+        self.inner
+            .lock()
+            .expect("session capture mutex should not be poisoned")
+            .events
+            .clone()
+    }
+
     fn push_event(&self, event: SessionEvent) {
         if let Ok(mut inner) = self.inner.lock() {
+            for listener in &inner.listeners {
+                listener(&event);
+            }
             inner.events.push(event);
         }
     }
 
-    fn render_html(&self) -> String {
+    pub fn render_html(&self) -> String {
         let (started_at, events) = {
             let inner = self
                 .inner
@@ -104,6 +196,7 @@ impl SessionCapture {
                 SessionEvent::UserInput { .. } => user_inputs += 1,
                 SessionEvent::ToolCall { .. } => tool_calls += 1,
                 SessionEvent::AssistantResponse { .. } => assistant_responses += 1,
+                SessionEvent::TaskFinished { .. } | SessionEvent::CommandOutput { .. } => {}
             }
         }
 
@@ -136,6 +229,8 @@ impl SessionCapture {
         out.push_str("    .event.user { border-left-color: #0a7c3e; }\n");
         out.push_str("    .event.tool { border-left-color: #0969da; }\n");
         out.push_str("    .event.assistant { border-left-color: #8250df; }\n");
+        out.push_str("    .event.finish { border-left-color: #bf8700; }\n");
+        out.push_str("    .event.output { border-left-color: #59636e; }\n");
         out.push_str("    .event h2 { margin: 0; font-size: 1rem; }\n");
         out.push_str("    .meta { margin-top: 0.2rem; color: #59636e; font-size: 0.85rem; }\n");
         out.push_str("    .label { font-weight: 600; margin: 0.8rem 0 0.3rem; display: block; }\n");
@@ -187,9 +282,13 @@ impl SessionCapture {
                 SessionEvent::ToolCall {
                     timestamp,
                     tool_name,
-                    params_json,
+                    params,
                     snapshot,
+                    cwd,
+                    title,
                 } => {
+                    let params_json = serde_json::to_string_pretty(params)
+                        .unwrap_or_else(|err| format!("serialization error: {err}"));
                     out.push_str("    <section class=\"event tool\">\n");
                     let _ = writeln!(
                         &mut out,
@@ -197,13 +296,16 @@ impl SessionCapture {
                         idx + 1,
                         escape_html(tool_name)
                     );
-                    let _ = writeln!(
-                        &mut out,
-                        "      <div class=\"meta\">{}</div>",
-                        escape_html(timestamp)
-                    );
+                    let mut meta = escape_html(timestamp);
+                    if let Some(cwd) = cwd {
+                        let _ = write!(&mut meta, " | in <code>{}</code>", escape_html(cwd));
+                    }
+                    if let Some(title) = title {
+                        let _ = write!(&mut meta, " | titled {}", escape_html(title));
+                    }
+                    let _ = writeln!(&mut out, "      <div class=\"meta\">{meta}</div>");
                     out.push_str("      <span class=\"label\">Parameters</span>\n");
-                    let _ = writeln!(&mut out, "      <pre>{}</pre>", escape_html(params_json));
+                    let _ = writeln!(&mut out, "      <pre>{}</pre>", escape_html(&params_json));
                     out.push_str("      <span class=\"label\">Tool Response Snapshot</span>\n");
                     let _ = writeln!(&mut out, "      <pre>{}</pre>", escape_html(snapshot));
                     out.push_str("    </section>\n");
@@ -227,6 +329,56 @@ impl SessionCapture {
                     );
                     out.push_str("    </section>\n");
                 }
+                SessionEvent::TaskFinished {
+                    timestamp,
+                    status,
+                    summary,
+                } => {
+                    out.push_str("    <section class=\"event finish\">\n");
+                    let _ = writeln!(
+                        &mut out,
+                        "      <h2>#{} Task Finished: {}</h2>",
+                        idx + 1,
+                        status.as_str()
+                    );
+                    let _ = writeln!(
+                        &mut out,
+                        "      <div class=\"meta\">{}</div>",
+                        escape_html(timestamp)
+                    );
+                    out.push_str("      <span class=\"label\">Summary</span>\n");
+                    let _ = writeln!(
+                        &mut out,
+                        "      <div class=\"assistant-body\">{}</div>",
+                        markdown_to_html(summary)
+                    );
+                    out.push_str("    </section>\n");
+                }
+                SessionEvent::CommandOutput {
+                    timestamp,
+                    exit_status,
+                    duration_seconds,
+                    output,
+                } => {
+                    let status = match exit_status {
+                        Some(exit) => format!("exit status {exit}"),
+                        None => "no exit status".to_string(),
+                    };
+                    out.push_str("    <section class=\"event output\">\n");
+                    let _ = writeln!(&mut out, "      <h2>#{} Command Output</h2>", idx + 1);
+                    let _ = writeln!(
+                        &mut out,
+                        "      <div class=\"meta\">{} | {status} after {duration_seconds:.1}s</div>",
+                        escape_html(timestamp)
+                    );
+                    let _ = writeln!(
+                        &mut out,
+                        "      <details><summary class=\"label\">{} lines</summary><pre>{}</pre></details>",
+                        output.lines().count(),
+                        escape_html(output)
+                    );
+                    out.push_str("    </section>\n");
+                }
             }
         }
 
@@ -269,6 +421,7 @@ fn push_escaped_char(out: &mut String, ch: char) {
 #[cfg(test)]
 mod tests {
     use super::SessionCapture;
+    use crate::agent::{TaskResult, TaskStatus};
     use serde_json::json;
 
     #[test]
@@ -279,6 +432,8 @@ mod tests {
             "raw_input",
             &json!({"str": "ls", "float": 0.4}),
             "output line\nCursor info: row=0, col=0, char=\"o\"",
+            Some("/srv/<app>"),
+            Some("vim"),
         );
         capture.record_assistant_response("**Done**\n\n`ls` returned output.");
 
@@ -286,6 +441,7 @@ mod tests {
         assert!(html.contains("User Input"));
         assert!(html.contains("Tool Call: raw_input"));
         assert!(html.contains("Tool Response Snapshot"));
+        assert!(html.contains("in <code>/srv/&lt;app&gt;</code> | titled vim"));
         assert!(html.contains("<strong>Done</strong>"));
         assert!(html.contains("<code>ls</code>"));
     }
@@ -294,13 +450,67 @@ mod tests {
     fn escapes_user_and_snapshot_content() {
         let capture = SessionCapture::new();
         capture.record_user_input("echo <unsafe>");
-        capture.record_tool_call("raw_input", &json!({"str": "<x>", "float": 0.1}), "<snap>");
+        capture.record_tool_call(
+            "raw_input",
+            &json!({"str": "<x>", "float": 0.1}),
+            "<snap>",
+            None,
+            None,
+        );
 
         let html = capture.render_html();
         assert!(html.contains("echo &lt;unsafe&gt;"));
         assert!(html.contains("&lt;snap&gt;"));
     }
 
+    #[test]
+    fn renders_one_json_object_per_event() {
+        let capture = SessionCapture::new();
+        capture.record_user_input("list files");
+        capture.record_tool_call(
+            "raw_input",
+            &json!({"str": "ls\\n", "float": 0.4}),
+            "a\nb",
+            Some("/tmp"),
+            None,
+        );
+        capture.record_assistant_response("Done.");
+
+        let jsonl = capture.render_jsonl().expect("render jsonl");
+        let lines: Vec<serde_json::Value> = jsonl
+            .lines()
+            .map(|line| serde_json::from_str(line).expect("valid json line"))
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0]["type"], "user_input");
+        assert_eq!(lines[0]["text"], "list files");
+        assert_eq!(lines[1]["type"], "tool_call");
+        assert_eq!(lines[1]["params"]["str"], "ls\\n");
+        assert_eq!(lines[1]["snapshot"], "a\nb");
+        assert_eq!(lines[1]["cwd"], "/tmp");
+        assert!(lines[1].get("title").is_none());
+        assert_eq!(lines[2]["type"], "assistant_response");
+        assert_eq!(lines[2]["markdown"], "Done.");
+    }
+
+    #[test]
+    fn records_task_result_in_both_formats() {
+        let capture = SessionCapture::new();
+        capture.record_task_finished(&TaskResult {
+            status: TaskStatus::Blocked,
+            summary: "Waiting on `sudo`.".to_string(),
+        });
+
+        let jsonl = capture.render_jsonl().expect("render jsonl");
+        let event: serde_json::Value = serde_json::from_str(jsonl.trim()).expect("valid json");
+        assert_eq!(event["type"], "task_finished");
+        assert_eq!(event["status"], "blocked");
+        let html = capture.render_html();
+        assert!(html.contains("Task Finished: blocked"));
+        assert!(html.contains("<code>sudo</code>"));
+    }
+
     #[test]
     fn renders_headings_lists_and_links() {
         let capture = SessionCapture::new();
@@ -0,0 +1,343 @@
+use anyhow::{Context, Result};
+use avt::Vt;
+use avt::util::TextCollector;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::ffi::{OsStr, OsString};
+use std::fs;
+use std::path::Path;
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+use crate::config::default_state_dir;
+use crate::terminal_queries::ShellMark;
+
+/// Runs before each command; the marks are emitted through PS0, which bash expands then.
+const BASH_PS0: &str = r"\e]133;C\a";
+/// Keeps `$?` for PS1 (bash restores it after PROMPT_COMMAND) and stops nested shells
+/// from inheriting the hooks.
+/// Also reports the directory with OSC 7, escaping `%` so the path decodes back exactly.
+const BASH_PROMPT_COMMAND: &str = r#"__gibberish_status=$?; export -n PS0 PROMPT_COMMAND; printf '\e]133;D;%s\a\e]7;file://%s%s\a\e]133;A\a' "$__gibberish_status" "$HOSTNAME" "${PWD//\%/%25}""#;
+/// Finished commands whose output is kept for the `command_output` tool.
+const KEPT_OUTPUTS: usize = 10;
+/// Per command; the oldest lines are dropped past this.
+const MAX_OUTPUT_BYTES: usize = 8 * 1024 * 1024;
+/// Sourced through ZDOTDIR, which it puts back before the user's own startup files run.
+const ZSH_ZSHENV: &str = r#"if [[ -n ${GIBBERISH_ZDOTDIR+x} ]]; then
+  ZDOTDIR=$GIBBERISH_ZDOTDIR
+  unset GIBBERISH_ZDOTDIR
+else
+  unset ZDOTDIR
+fi
+[[ -f ${ZDOTDIR:-$HOME}/.zshenv ]] && source ${ZDOTDIR:-$HOME}/.zshenv
+__gibberish_precmd() { print -n "\e]133;D;$?\a\e]7;file://$HOST${PWD//\%/%25}\a\e]133;A\a" }
+__gibberish_preexec() { print -n '\e]133;C\a' }
+precmd_functions=(__gibberish_precmd $precmd_functions)
+preexec_functions+=(__gibberish_preexec)
+"#;
+const FISH_INIT: &str = r"function __gibberish_preexec --on-event fish_preexec; printf '\e]133;C\a'; end; function __gibberish_postexec --on-event fish_postexec; printf '\e]133;D;%s\a\e]7;file://%s%s\a\e]133;A\a' $status $hostname (string replace -a % %25 -- $PWD); end";
+
+/// Adds the hooks that make `program` report command boundaries with OSC 133 marks.
+/// Shells other than bash, zsh, and fish are left alone.
+pub fn inject(
+    program: &str,
+    sandboxed: bool,
+    args: &mut Vec<String>,
+    environment: &mut BTreeMap<OsString, OsString>,
+) -> Result<()> {
+    let name = Path::new(program)
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned());
+    match name.as_deref() {
+        Some("bash") => {
+            prepend(environment, "PS0", BASH_PS0, "");
+            prepend(environment, "PROMPT_COMMAND", BASH_PROMPT_COMMAND, "; ");
+        }
+        Some("zsh") if sandboxed => {
+            warn!("zsh shell integration is unavailable in the sandbox");
+        }
+        Some("zsh") => {
+            let dir = default_state_dir()?.join("zsh-integration");
+            fs::create_dir_all(&dir)
+                .with_context(|| format!("failed to create {}", dir.display()))?;
+            let zshenv = dir.join(".zshenv");
+            fs::write(&zshenv, ZSH_ZSHENV)
+                .with_context(|| format!("failed to write {}", zshenv.display()))?;
+            if let Some(original) = environment.insert("ZDOTDIR".into(), dir.into_os_string()) {
+                environment.insert("GIBBERISH_ZDOTDIR".into(), original);
+            }
+        }
+        Some("fish") => {
+            args.insert(0, FISH_INIT.to_string());
+            args.insert(0, "--init-command".to_string());
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn prepend(environment: &mut BTreeMap<OsString, OsString>, key: &str, ours: &str, separator: &str) {
+    let mut value = OsString::from(ours);
+    if let Some(theirs) = environment
+        .get(OsStr::new(key))
+        .filter(|theirs| !theirs.is_empty())
+    {
+        value.push(separator);
+        value.push(theirs);
+    }
+    environment.insert(key.into(), value);
+}
+
+/// What the shell last reported about the command it is running.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CommandStatus {
+    pub running_for: Option<Duration>,
+    pub last_exit: Option<i32>,
+    pub last_duration: Option<Duration>,
+}
+
+impl CommandStatus {
+    /// One footer line for the snapshot.
+    pub fn describe(&self) -> String {
+        if let Some(running_for) = self.running_for {
+            return format!("Command: running for {:.1}s", running_for.as_secs_f64());
+        }
+        match (self.last_exit, self.last_duration) {
+            (Some(exit), Some(duration)) => format!(
+                "Command: idle; the last one exited with status {exit} after {:.1}s",
+                duration.as_secs_f64()
+            ),
+            (None, Some(duration)) => format!(
+                "Command: idle; the last one finished after {:.1}s",
+                duration.as_secs_f64()
+            ),
+            _ => "Command: idle".to_string(),
+        }
+    }
+}
+
+/// The text a finished command printed, wrapped the way the terminal showed it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CommandOutput {
+    /// Counts up from 1 for each command since the shell started.
+    pub id: u64,
+    pub exit: Option<i32>,
+    pub duration: Duration,
+    pub lines: Vec<String>,
+    /// Lines dropped from the start to stay under `MAX_OUTPUT_BYTES`.
+    pub dropped_lines: usize,
+}
+
+/// Follows OSC 133 marks to know whether a command is running, and keeps what the
+/// last few commands printed.
+#[derive(Default)]
+pub struct CommandTracker {
+    integrated: bool,
+    running: Option<RunningCommand>,
+    last_exit: Option<i32>,
+    last_duration: Option<Duration>,
+    finished_count: u64,
+    finished: VecDeque<CommandOutput>,
+}
+
+struct RunningCommand {
+    started: Instant,
+    collector: TextCollector,
+    output: OutputLines,
+}
+
+#[derive(Default)]
+struct OutputLines {
+    lines: VecDeque<String>,
+    bytes: usize,
+    dropped_lines: usize,
+}
+
+impl OutputLines {
+    fn push(&mut self, lines: impl IntoIterator<Item = String>) {
+        for line in lines {
+            self.bytes += line.len() + 1;
+            self.lines.push_back(line);
+        }
+        while self.bytes > MAX_OUTPUT_BYTES
+            && let Some(line) = self.lines.pop_front()
+        {
+            self.bytes -= line.len() + 1;
+            self.dropped_lines += 1;
+        }
+    }
+}
+
+impl CommandTracker {
+    /// `cols` and `rows` are the terminal size, so output wraps the way it did on screen.
+    pub fn apply(&mut self, mark: ShellMark, cols: usize, rows: usize) {
+        self.integrated = true;
+        match mark {
+            ShellMark::PromptStarted => {}
+            ShellMark::CommandStarted => {
+                let vt = Vt::builder().size(cols, rows).scrollback_limit(0).build();
+                self.running = Some(RunningCommand {
+                    started: Instant::now(),
+                    collector: TextCollector::new(vt),
+                    output: OutputLines::default(),
+                });
+            }
+            // Without a start mark this is the first prompt or an abandoned line.
+            ShellMark::CommandFinished(exit) => {
+                if let Some(RunningCommand {
+                    started,
+                    collector,
+                    mut output,
+                }) = self.running.take()
+                {
+                    let duration = started.elapsed();
+                    self.last_exit = exit;
+                    self.last_duration = Some(duration);
+                    output.push(collector.flush());
+                    self.finished_count += 1;
+                    if self.finished.len() == KEPT_OUTPUTS {
+                        self.finished.pop_front();
+                    }
+                    self.finished.push_back(CommandOutput {
+                        id: self.finished_count,
+                        exit,
+                        duration,
+                        lines: output.lines.into(),
+                        dropped_lines: output.dropped_lines,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Collects PTY output while a command runs.
+    pub fn record_output(&mut self, bytes: &[u8]) {
+        if let Some(command) = self.running.as_mut() {
+            let lines: Vec<String> = command
+                .collector
+                .feed_str(&String::from_utf8_lossy(bytes))
+                .collect();
+            command.output.push(lines);
+        }
+    }
+
+    /// `None` until the shell has sent a mark, so shells without integration look as before.
+    pub fn status(&self) -> Option<CommandStatus> {
+        self.integrated.then(|| CommandStatus {
+            running_for: self
+                .running
+                .as_ref()
+                .map(|command| command.started.elapsed()),
+            last_exit: self.last_exit,
+            last_duration: self.last_duration,
+        })
+    }
+
+    /// The `back`th most recent finished command (1 is the latest), if it is still kept.
+    pub fn output(&self, back: usize) -> Option<CommandOutput> {
+        back.checked_sub(1)
+            .and_then(|skip| self.finished.iter().rev().nth(skip))
+            .cloned()
+    }
+
+    /// Forgets everything about the old shell except how many commands it ran, so
+    /// command ids stay unique for the session.
+    pub fn restart(&mut self) {
+        *self = Self {
+            finished_count: self.finished_count,
+            ..Self::default()
+        };
+    }
+
+    /// Kept commands newer than the command id `seen`, oldest first.
+    pub fn finished_since(&self, seen: u64) -> impl Iterator<Item = &CommandOutput> {
+        self.finished.iter().filter(move |output| output.id > seen)
+    }
+
+    /// How many commands have finished since the session started.
+    pub fn finished_count(&self) -> u64 {
+        self.finished_count
+    }
+
+    /// How many finished commands `output` can return.
+    pub fn kept_outputs(&self) -> usize {
+        self.finished.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{CommandStatus, CommandTracker, inject};
+    use crate::terminal_queries::ShellMark;
+    use std::collections::BTreeMap;
+    use std::ffi::{OsStr, OsString};
+    use std::time::Duration;
+
+    #[test]
+    fn bash_hooks_run_before_existing_prompt_command() {
+        let mut args = Vec::new();
+        let mut environment = BTreeMap::from([(
+            OsString::from("PROMPT_COMMAND"),
+            OsString::from("history -a"),
+        )]);
+
+        inject("/bin/bash", false, &mut args, &mut environment).expect("inject");
+
+        assert!(args.is_empty());
+        assert_eq!(environment[OsStr::new("PS0")], r"\e]133;C\a");
+        let prompt_command = environment[OsStr::new("PROMPT_COMMAND")].to_string_lossy();
+        assert!(prompt_command.starts_with("__gibberish_status=$?;"));
+        assert!(prompt_command.ends_with("; history -a"));
+    }
+
+    #[test]
+    fn tracks_running_and_finished_commands() {
+        let mut tracker = CommandTracker::default();
+        assert_eq!(tracker.status(), None);
+
+        tracker.apply(ShellMark::CommandFinished(Some(0)), 10, 3);
+        tracker.apply(ShellMark::PromptStarted, 10, 3);
+        assert_eq!(
+            tracker.status().expect("status").describe(),
+            "Command: idle"
+        );
+
+        assert_eq!(tracker.output(1), None);
+
+        tracker.apply(ShellMark::CommandStarted, 10, 3);
+        assert!(tracker.status().expect("status").running_for.is_some());
+        tracker.record_output(b"one\r\ntwo two two!\r\n50%\r100%\r\nfour\r\nfive");
+        tracker.apply(ShellMark::CommandFinished(Some(2)), 10, 3);
+        let status = tracker.status().expect("status");
+        assert_eq!(status.running_for, None);
+        assert_eq!(status.last_exit, Some(2));
+
+        let output = tracker.output(1).expect("output");
+        assert_eq!(tracker.kept_outputs(), 1);
+        assert_eq!((output.id, output.exit), (1, Some(2)));
+        assert_eq!(
+            output.lines,
+            ["one", "two two two!", "100%", "four", "five"]
+        );
+        assert_eq!(tracker.output(2), None);
+    }
+
+    #[test]
+    fn describes_status_for_the_footer() {
+        let running = CommandStatus {
+            running_for: Some(Duration::from_millis(3200)),
+            last_exit: Some(0),
+            last_duration: Some(Duration::from_secs(1)),
+        };
+        let finished = CommandStatus {
+            running_for: None,
+            ..running.clone()
+        };
+
+        assert_eq!(running.describe(), "Command: running for 3.2s");
+        assert_eq!(
+            finished.describe(),
+            "Command: idle; the last one exited with status 0 after 1.0s"
+        );
+    }
+}
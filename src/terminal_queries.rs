@@ -0,0 +1,388 @@
+use avt::Vt;
+
+/// Longest CSI sequence worth remembering; anything longer is not one we answer.
+const MAX_SEQUENCE_LEN: usize = 32;
+/// OSC bodies carry paths and window titles, so they get more room.
+const MAX_OSC_LEN: usize = 4096;
+/// Colors reported for OSC 10/11/12 queries: light text on a black background.
+const FOREGROUND: &str = "rgb:d0d0/d0d0/d0d0";
+const BACKGROUND: &str = "rgb:0000/0000/0000";
+
+/// A question a program asked the terminal and is now waiting to have answered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalQuery {
+    /// DSR 6 (`ESC[6n`), or DECXCPR (`ESC[?6n`) when `private`.
+    CursorPosition { private: bool },
+    /// DSR 5 (`ESC[5n`).
+    Status,
+    /// Primary device attributes (`ESC[c`).
+    PrimaryAttributes,
+    /// Secondary device attributes (`ESC[>c`).
+    SecondaryAttributes,
+    /// OSC 10, 11 or 12 with `?`; the reply ends the way the query did.
+    Color { slot: u8, bel: bool },
+}
+
+/// An OSC 133 command boundary emitted by the shell integration.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellMark {
+    /// `A`: the shell is drawing its prompt.
+    PromptStarted,
+    /// `C`: a command line was accepted and is about to run.
+    CommandStarted,
+    /// `D;STATUS`: the command finished, with its exit status when reported.
+    CommandFinished(Option<i32>),
+}
+
+/// A DEC private mode that snapshots report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TerminalMode {
+    /// 47, 1047, or 1049.
+    AlternateScreen,
+    /// 2004.
+    BracketedPaste,
+}
+
+/// Something in the PTY output the worker has to act on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TerminalSignal {
+    Query(TerminalQuery),
+    Mark(ShellMark),
+    /// OSC 7 (`file://HOST/PATH`), with the path decoded.
+    WorkingDirectory(String),
+    /// OSC 0 or 2.
+    Title(String),
+    /// DECSET (`ESC[?Nh`) or DECRST (`ESC[?Nl`), keeping only the modes we track.
+    Modes {
+        modes: Vec<TerminalMode>,
+        enabled: bool,
+    },
+    /// `ESC c`, which turns every mode off.
+    FullReset,
+    /// BEL outside an escape sequence.
+    Bell,
+}
+
+impl TerminalQuery {
+    /// The bytes a VT220-class terminal with `vt`'s screen would send back.
+    pub fn reply(self, vt: &Vt) -> Vec<u8> {
+        match self {
+            Self::CursorPosition { private } => {
+                let (cols, _) = vt.size();
+                let cursor = vt.cursor();
+                // A cursor waiting to wrap still reports the last column.
+                let col = cursor.col.min(cols.saturating_sub(1)) + 1;
+                let row = cursor.row + 1;
+                let marker = if private { "?" } else { "" };
+                format!("\x1b[{marker}{row};{col}R").into_bytes()
+            }
+            Self::Status => b"\x1b[0n".to_vec(),
+            Self::PrimaryAttributes => b"\x1b[?62;22c".to_vec(),
+            Self::SecondaryAttributes => b"\x1b[>1;10;0c".to_vec(),
+            Self::Color { slot, bel } => {
+                let color = if slot == 11 { BACKGROUND } else { FOREGROUND };
+                let terminator = if bel { "\x07" } else { "\x1b\\" };
+                format!("\x1b]{slot};{color}{terminator}").into_bytes()
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+enum State {
+    #[default]
+    Ground,
+    Escape,
+    Csi(Vec<u8>),
+    Osc(Vec<u8>),
+    OscEscape(Vec<u8>),
+}
+
+/// Finds terminal queries and shell marks in PTY output, including ones split across reads.
+#[derive(Debug, Default)]
+pub struct QueryResponder {
+    state: State,
+}
+
+impl QueryResponder {
+    /// Returns each signal completed in `bytes` with the offset just past it, so a
+    /// reply can be computed from the screen as it was when the query was sent.
+    pub fn scan(&mut self, bytes: &[u8]) -> Vec<(usize, TerminalSignal)> {
+        let mut signals = Vec::new();
+        for (offset, &byte) in bytes.iter().enumerate() {
+            if let Some(signal) = self.advance(byte) {
+                signals.push((offset + 1, signal));
+            }
+        }
+        signals
+    }
+
+    fn advance(&mut self, byte: u8) -> Option<TerminalSignal> {
+        const ESC: u8 = 0x1b;
+        const BEL: u8 = 0x07;
+        let (next, query) = match (std::mem::take(&mut self.state), byte) {
+            (State::OscEscape(body), b'\\') => (State::Ground, parse_osc(&body, false)),
+            (State::Osc(body), BEL) => (State::Ground, parse_osc(&body, true)),
+            (State::Osc(body), ESC) => (State::OscEscape(body), None),
+            (State::Osc(mut body), _) => {
+                // One byte past the limit marks the body as too long to use.
+                if body.len() <= MAX_OSC_LEN {
+                    body.push(byte);
+                }
+                (State::Osc(body), None)
+            }
+            (_, ESC) => (State::Escape, None),
+            (State::Ground, BEL) => (State::Ground, Some(TerminalSignal::Bell)),
+            (State::Escape, b'c') => (State::Ground, Some(TerminalSignal::FullReset)),
+            (State::Escape, b'[') => (State::Csi(Vec::new()), None),
+            (State::Escape, b']') => (State::Osc(Vec::new()), None),
+            (State::Csi(mut params), 0x20..=0x3f) if params.len() < MAX_SEQUENCE_LEN => {
+                params.push(byte);
+                (State::Csi(params), None)
+            }
+            (State::Csi(params), 0x40..=0x7e) => (State::Ground, parse_csi(&params, byte)),
+            _ => (State::Ground, None),
+        };
+        self.state = next;
+        query
+    }
+}
+
+fn parse_csi(params: &[u8], final_byte: u8) -> Option<TerminalSignal> {
+    if let (Some(modes), b'h' | b'l') = (params.strip_prefix(b"?"), final_byte) {
+        return parse_modes(modes, final_byte == b'h');
+    }
+    let query = match (params, final_byte) {
+        (b"6", b'n') => Some(TerminalQuery::CursorPosition { private: false }),
+        (b"?6", b'n') => Some(TerminalQuery::CursorPosition { private: true }),
+        (b"5", b'n') => Some(TerminalQuery::Status),
+        (b"" | b"0", b'c') => Some(TerminalQuery::PrimaryAttributes),
+        (b">" | b">0", b'c') => Some(TerminalQuery::SecondaryAttributes),
+        _ => None,
+    };
+    query.map(TerminalSignal::Query)
+}
+
+fn parse_modes(params: &[u8], enabled: bool) -> Option<TerminalSignal> {
+    let modes: Vec<TerminalMode> = params
+        .split(|&byte| byte == b';')
+        .filter_map(|param| match param {
+            b"47" | b"1047" | b"1049" => Some(TerminalMode::AlternateScreen),
+            b"2004" => Some(TerminalMode::BracketedPaste),
+            _ => None,
+        })
+        .collect();
+    (!modes.is_empty()).then_some(TerminalSignal::Modes { modes, enabled })
+}
+
+fn parse_osc(body: &[u8], bel: bool) -> Option<TerminalSignal> {
+    if body.len() > MAX_OSC_LEN {
+        return None;
+    }
+    let slot = match body {
+        b"10;?" => 10,
+        b"11;?" => 11,
+        b"12;?" => 12,
+        _ => {
+            if let Some(title) = body
+                .strip_prefix(b"0;")
+                .or_else(|| body.strip_prefix(b"2;"))
+            {
+                // Titles end up in snapshots and captures, where control characters
+                // could move the cursor or start escape sequences of their own.
+                return Some(TerminalSignal::Title(
+                    String::from_utf8_lossy(title)
+                        .chars()
+                        .filter(|ch| !ch.is_control())
+                        .collect(),
+                ));
+            }
+            if let Some(url) = body.strip_prefix(b"7;") {
+                return parse_file_url(url).map(TerminalSignal::WorkingDirectory);
+            }
+            return parse_shell_mark(body).map(TerminalSignal::Mark);
+        }
+    };
+    Some(TerminalSignal::Query(TerminalQuery::Color { slot, bel }))
+}
+
+/// The percent-decoded path of a `file://HOST/PATH` URL; the host is ignored.
+fn parse_file_url(url: &[u8]) -> Option<String> {
+    let rest = url.strip_prefix(b"file://")?;
+    let path = &rest[rest.iter().position(|&byte| byte == b'/')?..];
+    let mut decoded = Vec::with_capacity(path.len());
+    let mut i = 0;
+    while i < path.len() {
+        let escaped = (path[i] == b'%')
+            .then(|| path.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            None => {
+                decoded.push(path[i]);
+                i += 1;
+            }
+        }
+    }
+    Some(String::from_utf8_lossy(&decoded).into_owned())
+}
+
+fn parse_shell_mark(body: &[u8]) -> Option<ShellMark> {
+    let mut fields = std::str::from_utf8(body.strip_prefix(b"133;")?)
+        .ok()?
+        .split(';');
+    match fields.next()? {
+        "A" => Some(ShellMark::PromptStarted),
+        "C" => Some(ShellMark::CommandStarted),
+        "D" => Some(ShellMark::CommandFinished(
+            fields.next().and_then(|status| status.parse().ok()),
+        )),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{QueryResponder, ShellMark, TerminalMode, TerminalQuery, TerminalSignal};
+    use avt::Vt;
+
+    #[test]
+    fn finds_queries_split_across_reads() {
+        let mut responder = QueryResponder::default();
+
+        assert_eq!(responder.scan(b"ab\x1b["), Vec::new());
+        assert_eq!(
+            responder.scan(b"6nc\x1b[>c\x1b]11"),
+            vec![
+                (
+                    2,
+                    TerminalSignal::Query(TerminalQuery::CursorPosition { private: false })
+                ),
+                (7, TerminalSignal::Query(TerminalQuery::SecondaryAttributes)),
+            ]
+        );
+        assert_eq!(
+            responder.scan(b";?\x1b\\\x1b[1;31m\x1b[c"),
+            vec![
+                (
+                    4,
+                    TerminalSignal::Query(TerminalQuery::Color {
+                        slot: 11,
+                        bel: false
+                    })
+                ),
+                (14, TerminalSignal::Query(TerminalQuery::PrimaryAttributes)),
+            ]
+        );
+        assert_eq!(
+            responder.scan(b"\x1b]0;title\x07\x1b[?6n"),
+            vec![
+                (10, TerminalSignal::Title("title".to_string())),
+                (
+                    15,
+                    TerminalSignal::Query(TerminalQuery::CursorPosition { private: true })
+                )
+            ]
+        );
+    }
+
+    #[test]
+    fn finds_working_directory_and_title() {
+        let mut responder = QueryResponder::default();
+
+        assert_eq!(
+            responder.scan(b"\x1b]7;file://host/home/me/my%20dir\x1b\\\x1b]2;vim notes.md\x07"),
+            vec![
+                (
+                    34,
+                    TerminalSignal::WorkingDirectory("/home/me/my dir".to_string())
+                ),
+                (51, TerminalSignal::Title("vim notes.md".to_string())),
+            ]
+        );
+        assert_eq!(
+            responder.scan("\x1b]0;a\rb\x08c\u{9b}2Jd\x07".as_bytes()),
+            vec![(15, TerminalSignal::Title("abc2Jd".to_string()))]
+        );
+        let long_path = format!("\x1b]7;file://host/{}\x07", "a".repeat(5000));
+        assert_eq!(responder.scan(long_path.as_bytes()), Vec::new());
+    }
+
+    #[test]
+    fn finds_shell_marks() {
+        let mut responder = QueryResponder::default();
+
+        assert_eq!(
+            responder.scan(b"\x1b]133;D;130\x07\x1b]133;A\x07$ \x1b]133;C\x1b\\"),
+            vec![
+                (
+                    12,
+                    TerminalSignal::Mark(ShellMark::CommandFinished(Some(130)))
+                ),
+                (20, TerminalSignal::Mark(ShellMark::PromptStarted)),
+                (31, TerminalSignal::Mark(ShellMark::CommandStarted)),
+            ]
+        );
+        assert_eq!(
+            responder.scan(b"\x1b]133;D\x07"),
+            vec![(8, TerminalSignal::Mark(ShellMark::CommandFinished(None)))]
+        );
+    }
+
+    #[test]
+    fn finds_modes_and_bells() {
+        let mut responder = QueryResponder::default();
+
+        assert_eq!(
+            responder.scan(b"\x1b[?1;1049h\x07\x1b]0;t\x07\x1b[?2004l\x1b[?25l\x1bc"),
+            vec![
+                (
+                    10,
+                    TerminalSignal::Modes {
+                        modes: vec![TerminalMode::AlternateScreen],
+                        enabled: true
+                    }
+                ),
+                (11, TerminalSignal::Bell),
+                (17, TerminalSignal::Title("t".to_string())),
+                (
+                    25,
+                    TerminalSignal::Modes {
+                        modes: vec![TerminalMode::BracketedPaste],
+                        enabled: false
+                    }
+                ),
+                (33, TerminalSignal::FullReset),
+            ]
+        );
+    }
+
+    #[test]
+    fn replies_with_the_screen_state() {
+        let mut vt = Vt::new(10, 5);
+        vt.feed_str("\x1b[3;4Hxy");
+
+        assert_eq!(
+            TerminalQuery::CursorPosition { private: false }.reply(&vt),
+            b"\x1b[3;6R"
+        );
+        vt.feed_str("\x1b[3;9Hxy");
+        assert_eq!(
+            TerminalQuery::CursorPosition { private: true }.reply(&vt),
+            b"\x1b[?3;10R"
+        );
+        assert_eq!(
+            TerminalQuery::Color {
+                slot: 11,
+                bel: true
+            }
+            .reply(&vt),
+            b"\x1b]11;rgb:0000/0000/0000\x07"
+        );
+    }
+}
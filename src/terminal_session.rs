@@ -4,14 +4,26 @@ use pty_process::Size;
 use pty_process::blocking::{Command as PtyCommand, Pty, open};
 use rustix::fs::{OFlags, fcntl_getfl, fcntl_setfl};
 use rustix::process::{Pid, Signal, kill_process_group};
+use rustix::termios::tcgetpgrp;
+use serde::{Deserialize, Serialize};
 use std::io::{self, ErrorKind, Read, Write};
 use std::process::Child;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
 use std::thread;
 use std::time::{Duration, Instant};
-use tokio::sync::oneshot;
+use tokio::sync::{mpsc as tokio_mpsc, oneshot};
 
 use crate::config::SessionConfig;
+use crate::foreground::{ForegroundProcess, JobSignal, foreground_job};
+use crate::landlock_policy::LandlockPolicy;
+use crate::process_tree::ProcessTree;
+use crate::resource_limits::{
+    ProcessLimit, ShellCgroup, ShellRlimits, describe_command_exit, describe_limit_exit,
+};
+use crate::sandbox::SandboxPlan;
+use crate::shell_integration::{self, CommandOutput, CommandStatus, CommandTracker};
+use crate::terminal_queries::{QueryResponder, TerminalMode, TerminalSignal};
 
 const WORKER_TICK: Duration = Duration::from_millis(15);
 const SHUTDOWN_POLL_TICK: Duration = Duration::from_millis(20);
@@ -22,18 +34,91 @@ enum SessionCommand {
     SendInput(Vec<u8>, oneshot::Sender<Result<()>>),
     Snapshot(oneshot::Sender<Result<TerminalSnapshot>>),
     Reset(oneshot::Sender<Result<()>>),
+    TakeLimitNotices(oneshot::Sender<Vec<String>>),
+    SubscribeOutput(Subscriber, oneshot::Sender<TerminalSnapshot>),
+    Resize(u16, u16, oneshot::Sender<Result<()>>),
+    CommandOutput(usize, oneshot::Sender<CommandOutputs>),
+    Signal(JobSignal, oneshot::Sender<Result<()>>),
+    Processes(oneshot::Sender<Result<ProcessTree>>),
     Shutdown(oneshot::Sender<Result<()>>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TerminalSnapshot {
     pub cols: usize,
     pub rows: usize,
     pub cursor: Option<(usize, usize)>,
     pub lines: Vec<String>,
+    /// Reported by the shell integration; `None` when the shell has none.
+    #[serde(default)]
+    pub command: Option<CommandStatus>,
+    /// The shell's directory, from OSC 7.
+    #[serde(default)]
+    pub cwd: Option<String>,
+    /// The window title, from OSC 0 or 2.
+    #[serde(default)]
+    pub title: Option<String>,
+    /// Left out when `shell.report_foreground` is off or the shell has exited.
+    #[serde(default)]
+    pub foreground: Option<ForegroundProcess>,
+    #[serde(default)]
+    pub modes: TerminalModes,
+    /// Times the bell rang since the session started.
+    #[serde(default)]
+    pub bell_count: u64,
+    /// Bells the reader has not seen yet; see [`TerminalSnapshot::count_new_bells`].
+    #[serde(default)]
+    pub new_bells: u64,
+}
+
+/// Modes programs switched on that change which screen shows or what keys should send.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TerminalModes {
+    pub alternate_screen: bool,
+    /// Arrow keys send `ESC O A` rather than `ESC [ A`.
+    pub application_cursor: bool,
+    pub bracketed_paste: bool,
+}
+
+/// What the shell and the programs in it last told the terminal about themselves.
+#[derive(Debug, Default)]
+struct Reported {
+    cwd: Option<String>,
+    title: Option<String>,
+    alternate_screen: bool,
+    bracketed_paste: bool,
+    bells: u64,
+}
+
+impl Reported {
+    fn set_modes(&mut self, modes: &[TerminalMode], enabled: bool) {
+        for mode in modes {
+            match mode {
+                TerminalMode::AlternateScreen => self.alternate_screen = enabled,
+                TerminalMode::BracketedPaste => self.bracketed_paste = enabled,
+            }
+        }
+    }
+}
+
+/// What the worker kept of a finished command's output.
+#[derive(Debug, Clone)]
+pub struct CommandOutputs {
+    /// Whether the shell has sent any OSC 133 marks.
+    pub integrated: bool,
+    /// How many finished commands are kept.
+    pub kept: usize,
+    pub output: Option<CommandOutput>,
 }
 
 impl TerminalSnapshot {
+    /// Sets `new_bells` to the bells rung since the snapshot whose count is in `seen`, and
+    /// leaves this snapshot's count there for next time.
+    pub fn count_new_bells(&mut self, seen: &AtomicU64) {
+        let previous = seen.swap(self.bell_count, Ordering::Relaxed);
+        self.new_bells = self.bell_count.saturating_sub(previous);
+    }
+
     pub fn render(&self) -> String {
         let mut rendered_lines: Vec<String> = self
             .lines
@@ -72,9 +157,60 @@ impl TerminalSnapshot {
         if !rendered.is_empty() {
             rendered.push('\n');
         }
+        if let Some(title) = &self.title {
+            rendered.push_str(&format!("Title: {title}\n"));
+        }
+        if let Some(cwd) = &self.cwd {
+            rendered.push_str(&format!("Working directory: {cwd}\n"));
+        }
+        if let Some(foreground) = &self.foreground {
+            rendered.push_str(&foreground.describe());
+            rendered.push('\n');
+        }
+        if let Some(command) = &self.command {
+            rendered.push_str(&command.describe());
+            rendered.push('\n');
+        }
+        if let Some(terminal) = self.describe_terminal() {
+            rendered.push_str(&terminal);
+            rendered.push('\n');
+        }
         rendered.push_str(&footer);
         rendered
     }
+
+    /// One footer line with the modes that are on and any new bells.
+    fn describe_terminal(&self) -> Option<String> {
+        let mut parts = Vec::new();
+        if self.modes.alternate_screen {
+            parts.push("alternate screen".to_string());
+        }
+        if self.modes.application_cursor {
+            parts.push("application cursor keys".to_string());
+        }
+        if self.modes.bracketed_paste {
+            parts.push("bracketed paste".to_string());
+        }
+        match self.new_bells {
+            0 => {}
+            1 => parts.push("the bell rang since the last snapshot".to_string()),
+            bells => parts.push(format!(
+                "the bell rang {bells} times since the last snapshot"
+            )),
+        }
+        (!parts.is_empty()).then(|| format!("Terminal: {}", parts.join(", ")))
+    }
+
+    /// Escape sequence that clears a real terminal and paints this screen's text and cursor.
+    pub fn redraw_sequence(&self) -> String {
+        let mut out = String::from("\x1b[0m\x1b[H\x1b[2J");
+        let lines: Vec<&str> = self.lines.iter().map(|line| line.trim_end()).collect();
+        out.push_str(&lines.join("\r\n"));
+        if let Some((col, row)) = self.cursor {
+            out.push_str(&format!("\x1b[{};{}H", row + 1, col + 1));
+        }
+        out
+    }
 }
 
 fn escape_display_char(ch: char) -> String {
@@ -177,6 +313,103 @@ impl TerminalSessionHandle {
             .await
             .context("terminal worker dropped reset acknowledgement")?
     }
+
+    /// Drains notices about processes killed by the configured resource limits.
+    pub async fn take_limit_notices(&self) -> Result<Vec<String>> {
+        let (notices_tx, notices_rx) = oneshot::channel();
+        self.cmd_tx
+            .send(SessionCommand::TakeLimitNotices(notices_tx))
+            .context("terminal worker is not running")?;
+
+        notices_rx
+            .await
+            .context("terminal worker dropped limit notices")
+    }
+
+    /// Streams raw PTY output from now on, starting from the returned snapshot.
+    pub async fn subscribe_output(
+        &self,
+    ) -> Result<(TerminalSnapshot, tokio_mpsc::UnboundedReceiver<Vec<u8>>)> {
+        self.subscribe(false).await
+    }
+
+    /// Like `subscribe_output`, for a real terminal that answers the program's queries
+    /// itself. The session stops answering them until the receiver is dropped, so the
+    /// program does not get two replies.
+    pub async fn attach_output(
+        &self,
+    ) -> Result<(TerminalSnapshot, tokio_mpsc::UnboundedReceiver<Vec<u8>>)> {
+        self.subscribe(true).await
+    }
+
+    async fn subscribe(
+        &self,
+        answers_queries: bool,
+    ) -> Result<(TerminalSnapshot, tokio_mpsc::UnboundedReceiver<Vec<u8>>)> {
+        let (output_tx, output_rx) = tokio_mpsc::unbounded_channel();
+        let (snap_tx, snap_rx) = oneshot::channel();
+        let subscriber = Subscriber {
+            output: output_tx,
+            answers_queries,
+        };
+        self.cmd_tx
+            .send(SessionCommand::SubscribeOutput(subscriber, snap_tx))
+            .context("terminal worker is not running")?;
+
+        let snapshot = snap_rx
+            .await
+            .context("terminal worker dropped output subscription")?;
+        Ok((snapshot, output_rx))
+    }
+
+    pub async fn resize(&self, cols: u16, rows: u16) -> Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.cmd_tx
+            .send(SessionCommand::Resize(cols, rows, ack_tx))
+            .context("terminal worker is not running")?;
+
+        ack_rx
+            .await
+            .context("terminal worker dropped resize acknowledgement")?
+    }
+
+    /// The output of the `back`th most recent finished command, 1 being the latest.
+    pub async fn command_output(&self, back: usize) -> Result<CommandOutputs> {
+        let (output_tx, output_rx) = oneshot::channel();
+        self.cmd_tx
+            .send(SessionCommand::CommandOutput(back, output_tx))
+            .context("terminal worker is not running")?;
+
+        output_rx
+            .await
+            .context("terminal worker dropped command output")
+    }
+
+    /// Sends `signal` to the foreground job's process group, the way the terminal would
+    /// for Ctrl-C, but without relying on the terminal's own settings.
+    pub async fn signal_foreground(&self, signal: JobSignal) -> Result<()> {
+        let (ack_tx, ack_rx) = oneshot::channel();
+        self.cmd_tx
+            .send(SessionCommand::Signal(signal, ack_tx))
+            .context("terminal worker is not running")?;
+
+        ack_rx
+            .await
+            .context("terminal worker dropped signal acknowledgement")?
+    }
+
+    /// The shell and every process under it, read from `/proc` without touching the
+    /// terminal.
+    pub async fn processes(&self) -> Result<ProcessTree> {
+        let (tree_tx, tree_rx) = oneshot::channel();
+        self.cmd_tx
+            .send(SessionCommand::Processes(tree_tx))
+            .context("terminal worker is not running")?;
+
+        tree_rx
+            .await
+            .context("terminal worker dropped process tree")?
+    }
 }
 
 impl Drop for TerminalSession {
@@ -198,12 +431,12 @@ fn run_worker(
     cmd_rx: Receiver<SessionCommand>,
     ready_tx: oneshot::Sender<Result<()>>,
 ) -> Result<()> {
-    let (cols, rows) = options.terminal_size()?;
-    let cols_u16 = u16::try_from(cols).context("terminal columns exceed u16 range")?;
-    let rows_u16 = u16::try_from(rows).context("terminal rows exceed u16 range")?;
+    let (mut cols, mut rows) = options.terminal_size()?;
+    let mut cols_u16 = u16::try_from(cols).context("terminal columns exceed u16 range")?;
+    let mut rows_u16 = u16::try_from(rows).context("terminal rows exceed u16 range")?;
 
     let setup = spawn_terminal_parts(&options, cols, rows, cols_u16, rows_u16);
-    let (mut pty, mut child, mut vt) = match setup {
+    let (mut pty, mut child, mut vt, mut cgroup) = match setup {
         Ok(parts) => {
             let _ = ready_tx.send(Ok(()));
             parts
@@ -217,10 +450,26 @@ fn run_worker(
     let mut read_buf = [0_u8; 8192];
     let mut child_exited = false;
     let mut running = true;
+    let mut limit_notices = Vec::new();
+    // The last command whose exit status was checked against the limits.
+    let mut limits_checked = 0;
+    let mut process_limit = ProcessLimit::default();
+    let mut subscribers = Vec::new();
+    let mut queries = QueryResponder::default();
+    let mut commands = CommandTracker::default();
+    let mut reported = Reported::default();
 
     while running {
         if !child_exited {
-            match drain_pty_output(&mut pty, &mut vt, &mut read_buf) {
+            match drain_pty_output(
+                &mut pty,
+                &mut vt,
+                &mut queries,
+                &mut commands,
+                &mut reported,
+                &mut read_buf,
+                &mut subscribers,
+            ) {
                 Ok(is_eof) => {
                     if is_eof {
                         child_exited = true;
@@ -229,8 +478,9 @@ fn run_worker(
                 Err(err) => return Err(err).context("failed to process PTY output"),
             }
 
-            if let Some(_status) = child.try_wait().context("failed to poll bash process")? {
+            if let Some(status) = child.try_wait().context("failed to poll bash process")? {
                 child_exited = true;
+                limit_notices.extend(describe_limit_exit(status, &options.limits));
             }
         }
 
@@ -245,30 +495,129 @@ fn run_worker(
             }
             Ok(SessionCommand::Snapshot(reply)) => {
                 if !child_exited {
-                    let _ = drain_pty_output(&mut pty, &mut vt, &mut read_buf);
+                    let _ = drain_pty_output(
+                        &mut pty,
+                        &mut vt,
+                        &mut queries,
+                        &mut commands,
+                        &mut reported,
+                        &mut read_buf,
+                        &mut subscribers,
+                    );
                 }
-
-                let snapshot = TerminalSnapshot {
-                    cols,
-                    rows,
-                    cursor: vt.cursor().into(),
-                    lines: vt.view().map(|line| line.text()).collect(),
-                };
-                let _ = reply.send(Ok(snapshot));
+                let foreground = foreground_process(&options, &pty, child_exited);
+                let _ = reply.send(Ok(snapshot_vt(&vt, &commands, &reported, foreground)));
             }
             Ok(SessionCommand::Reset(ack)) => {
                 let res = (|| -> Result<()> {
-                    let (new_pty, new_child, new_vt) =
+                    let (new_pty, new_child, new_vt, new_cgroup) =
                         spawn_terminal_parts(&options, cols, rows, cols_u16, rows_u16)?;
                     terminate_bash_and_children(&mut child);
                     pty = new_pty;
                     child = new_child;
                     vt = new_vt;
+                    queries = QueryResponder::default();
+                    commands.restart();
+                    // Bells keep counting so readers' tallies stay valid.
+                    reported = Reported {
+                        bells: reported.bells,
+                        ..Reported::default()
+                    };
+                    cgroup = new_cgroup;
                     child_exited = false;
+                    limit_notices.clear();
                     Ok(())
                 })();
                 let _ = ack.send(res);
             }
+            Ok(SessionCommand::TakeLimitNotices(reply)) => {
+                limit_notices.extend(cgroup.as_mut().and_then(ShellCgroup::take_notice));
+                let exits: Vec<i32> = commands
+                    .finished_since(limits_checked)
+                    .filter_map(|output| output.exit)
+                    .collect();
+                limits_checked = commands.finished_count();
+                limit_notices.extend(
+                    exits
+                        .iter()
+                        .filter_map(|&exit| describe_command_exit(exit, &options.limits)),
+                );
+                limit_notices.extend(process_limit.check(&exits, &options.limits));
+                let _ = reply.send(std::mem::take(&mut limit_notices));
+            }
+            Ok(SessionCommand::SubscribeOutput(output, reply)) => {
+                if !child_exited {
+                    let _ = drain_pty_output(
+                        &mut pty,
+                        &mut vt,
+                        &mut queries,
+                        &mut commands,
+                        &mut reported,
+                        &mut read_buf,
+                        &mut subscribers,
+                    );
+                }
+                let foreground = foreground_process(&options, &pty, child_exited);
+                if reply
+                    .send(snapshot_vt(&vt, &commands, &reported, foreground))
+                    .is_ok()
+                {
+                    subscribers.push(output);
+                }
+            }
+            Ok(SessionCommand::Resize(new_cols, new_rows, ack)) => {
+                let res = pty
+                    .resize(Size::new(new_rows, new_cols))
+                    .context("failed to resize PTY");
+                if res.is_ok() {
+                    (cols_u16, rows_u16) = (new_cols, new_rows);
+                    (cols, rows) = (usize::from(new_cols), usize::from(new_rows));
+                    vt.resize(cols, rows);
+                }
+                let _ = ack.send(res);
+            }
+            Ok(SessionCommand::CommandOutput(back, reply)) => {
+                if !child_exited {
+                    let _ = drain_pty_output(
+                        &mut pty,
+                        &mut vt,
+                        &mut queries,
+                        &mut commands,
+                        &mut reported,
+                        &mut read_buf,
+                        &mut subscribers,
+                    );
+                }
+                let _ = reply.send(CommandOutputs {
+                    integrated: commands.status().is_some(),
+                    kept: commands.kept_outputs(),
+                    output: commands.output(back),
+                });
+            }
+            Ok(SessionCommand::Signal(signal, ack)) => {
+                let res = if child_exited {
+                    Err(anyhow::anyhow!("bash process has already exited"))
+                } else {
+                    let spawned = Pid::from_child(&child).as_raw_nonzero().get();
+                    foreground_job(&pty, spawned, options.sandbox.enabled).and_then(|group| {
+                        signal_process_group(group, signal.signal())
+                            .with_context(|| format!("failed to send {signal}"))
+                    })
+                };
+                let _ = ack.send(res);
+            }
+            Ok(SessionCommand::Processes(reply)) => {
+                let res = if child_exited {
+                    Err(anyhow::anyhow!("bash process has already exited"))
+                } else {
+                    let spawned = Pid::from_child(&child).as_raw_nonzero().get();
+                    let foreground = tcgetpgrp(&pty)
+                        .ok()
+                        .map(|group| group.as_raw_nonzero().get());
+                    ProcessTree::read(spawned, options.sandbox.enabled, foreground)
+                };
+                let _ = reply.send(res);
+            }
             Ok(SessionCommand::Shutdown(ack)) => {
                 let _ = ack.send(Ok(()));
                 running = false;
@@ -288,20 +637,77 @@ fn spawn_terminal_parts(
     rows: usize,
     cols_u16: u16,
     rows_u16: u16,
-) -> Result<(Pty, Child, Vt)> {
+) -> Result<(Pty, Child, Vt, Option<ShellCgroup>)> {
     let (pty, pts) = open().context("failed to open PTY master")?;
     set_pty_nonblocking(&pty).context("failed to set PTY nonblocking mode")?;
     pty.resize(Size::new(rows_u16, cols_u16))
         .context("failed to resize PTY")?;
-    let child = PtyCommand::new(&options.shell.program)
-        // `pty-process` defaults to canonical (cooked) mode, which is what we want:
-        // control bytes like Ctrl-C/Ctrl-Z become terminal-generated signals.
-        .args(&options.shell.args)
-        .envs(&options.shell.env)
-        .spawn(pts)
-        .context("failed to spawn bash process")?;
+    let shell_cwd = options.shell_cwd()?;
+    let mut args = options.shell.effective_args();
+    let mut environment = options.shell.environment();
+    if options.shell.integration {
+        shell_integration::inject(
+            &options.shell.program,
+            options.sandbox.enabled,
+            &mut args,
+            &mut environment,
+        )?;
+    }
+    let arg0 = options.shell.arg0();
+    let sandbox = SandboxPlan::from_config(&options.sandbox, &shell_cwd)?.map(|plan| SandboxPlan {
+        arg0: arg0.clone(),
+        ..plan
+    });
+    let command = match sandbox.as_ref() {
+        Some(plan) => {
+            let (exe, argv) = plan.wrap_command(&options.shell.program, &args)?;
+            PtyCommand::new(exe).args(argv)
+        }
+        None => {
+            let command = PtyCommand::new(&options.shell.program).args(&args);
+            match arg0.as_ref() {
+                Some(arg0) => command.arg0(arg0),
+                None => command,
+            }
+        }
+    };
+    // `pty-process` defaults to canonical (cooked) mode, which is what we want:
+    // control bytes like Ctrl-C/Ctrl-Z become terminal-generated signals.
+    let mut command = command
+        .current_dir(&shell_cwd)
+        .env_clear()
+        .envs(environment);
+    if let Some(plan) = sandbox.as_ref() {
+        let (key, value) = plan.env()?;
+        command = command.env(key, value);
+    }
+
+    // pty-process has a single `pre_exec` slot, so every child-side step is chained here.
+    // Each hook only makes syscalls on state prepared before fork.
+    let mut hooks: Vec<Box<dyn Fn() -> io::Result<()> + Send + Sync>> = Vec::new();
+    let cgroup = ShellCgroup::create(&options.limits)?;
+    if let Some(cgroup) = cgroup.as_ref() {
+        let entry = cgroup.entry()?;
+        hooks.push(Box::new(move || entry.enter()));
+    }
+    let rlimits = ShellRlimits::from_config(&options.limits);
+    if !rlimits.is_empty() {
+        hooks.push(Box::new(move || rlimits.apply()));
+    }
+    if let Some(policy) = LandlockPolicy::from_config(&options.landlock, &shell_cwd)? {
+        let landlock = policy.prepare()?;
+        hooks.push(Box::new(move || landlock.restrict_self()));
+    }
+    if !hooks.is_empty() {
+        // SAFETY: the hooks write to an fd, call setrlimit, or call prctl and
+        // landlock_restrict_self on an fd, all on state built before fork; none allocates
+        // or takes a lock another thread could hold.
+        command = unsafe { command.pre_exec(move || hooks.iter().try_for_each(|hook| hook())) };
+    }
+
+    let child = command.spawn(pts).context("failed to spawn bash process")?;
     let vt = Vt::builder().size(cols, rows).scrollback_limit(0).build();
-    Ok((pty, child, vt))
+    Ok((pty, child, vt, cgroup))
 }
 
 async fn join_worker(worker: thread::JoinHandle<Result<()>>) -> Result<()> {
@@ -321,13 +727,93 @@ fn set_pty_nonblocking(pty: &Pty) -> io::Result<()> {
     fcntl_setfl(pty, flags).map_err(io::Error::from)
 }
 
-fn drain_pty_output(pty: &mut Pty, vt: &mut Vt, read_buf: &mut [u8]) -> io::Result<bool> {
+fn snapshot_vt(
+    vt: &Vt,
+    commands: &CommandTracker,
+    reported: &Reported,
+    foreground: Option<ForegroundProcess>,
+) -> TerminalSnapshot {
+    let (cols, rows) = vt.size();
+    TerminalSnapshot {
+        cols,
+        rows,
+        cursor: vt.cursor().into(),
+        lines: vt.view().map(|line| line.text()).collect(),
+        command: commands.status(),
+        cwd: reported.cwd.clone(),
+        title: reported.title.clone(),
+        foreground,
+        modes: TerminalModes {
+            alternate_screen: reported.alternate_screen,
+            application_cursor: vt.cursor_key_app_mode(),
+            bracketed_paste: reported.bracketed_paste,
+        },
+        bell_count: reported.bells,
+        new_bells: 0,
+    }
+}
+
+fn foreground_process(
+    options: &SessionConfig,
+    pty: &Pty,
+    child_exited: bool,
+) -> Option<ForegroundProcess> {
+    if !options.shell.report_foreground || child_exited {
+        return None;
+    }
+    ForegroundProcess::detect(pty)
+}
+
+/// Feeds PTY output to `vt`, answers any terminal queries in it on the emulator's behalf,
+/// and follows the shell's command marks, directory, and window title.
+fn drain_pty_output(
+    pty: &mut Pty,
+    vt: &mut Vt,
+    queries: &mut QueryResponder,
+    commands: &mut CommandTracker,
+    reported: &mut Reported,
+    read_buf: &mut [u8],
+    subscribers: &mut Vec<Subscriber>,
+) -> io::Result<bool> {
     loop {
         match pty.read(read_buf) {
             Ok(0) => return Ok(true),
             Ok(n) => {
-                let chunk = String::from_utf8_lossy(&read_buf[..n]);
-                vt.feed_str(&chunk);
+                let attached = subscribers
+                    .iter()
+                    .any(|subscriber| subscriber.answers_queries && !subscriber.output.is_closed());
+                let mut fed = 0;
+                for (end, signal) in queries.scan(&read_buf[..n]) {
+                    vt.feed_str(&String::from_utf8_lossy(&read_buf[fed..end]));
+                    commands.record_output(&read_buf[fed..end]);
+                    fed = end;
+                    match signal {
+                        TerminalSignal::Query(_) if attached => {}
+                        TerminalSignal::Query(query) => {
+                            write_all_with_retry(pty, &query.reply(vt))?
+                        }
+                        TerminalSignal::Mark(mark) => {
+                            let (cols, rows) = vt.size();
+                            commands.apply(mark, cols, rows);
+                        }
+                        TerminalSignal::WorkingDirectory(cwd) => reported.cwd = Some(cwd),
+                        TerminalSignal::Title(title) => {
+                            reported.title = Some(title).filter(|title| !title.is_empty());
+                        }
+                        TerminalSignal::Modes { modes, enabled } => {
+                            reported.set_modes(&modes, enabled);
+                        }
+                        TerminalSignal::FullReset => {
+                            reported.alternate_screen = false;
+                            reported.bracketed_paste = false;
+                        }
+                        TerminalSignal::Bell => reported.bells += 1,
+                    }
+                }
+                vt.feed_str(&String::from_utf8_lossy(&read_buf[fed..n]));
+                commands.record_output(&read_buf[fed..n]);
+                subscribers
+                    .retain(|subscriber| subscriber.output.send(read_buf[..n].to_vec()).is_ok());
             }
             Err(err) if err.raw_os_error() == Some(libc::EIO) => return Ok(true),
             Err(err) if err.kind() == ErrorKind::WouldBlock => return Ok(false),
@@ -337,6 +823,13 @@ fn drain_pty_output(pty: &mut Pty, vt: &mut Vt, read_buf: &mut [u8]) -> io::Resu
     }
 }
 
+/// Someone streaming the raw PTY output.
+struct Subscriber {
+    output: tokio_mpsc::UnboundedSender<Vec<u8>>,
+    /// A human's terminal, which answers queries in the output on its own.
+    answers_queries: bool,
+}
+
 fn write_all_with_retry(pty: &mut Pty, mut bytes: &[u8]) -> io::Result<()> {
     while !bytes.is_empty() {
         match pty.write(bytes) {
@@ -407,7 +900,28 @@ fn wait_for_child_exit(child: &mut Child, timeout: Duration) -> io::Result<bool>
 
 #[cfg(test)]
 mod tests {
-    use super::TerminalSnapshot;
+    use super::{TerminalModes, TerminalSession, TerminalSnapshot};
+    use crate::config::{InheritEnv, SessionConfig};
+    use crate::foreground::{ForegroundActivity, JobSignal};
+    use crate::test_support::{shell_session_config, wait_for_prompt};
+    use std::sync::atomic::AtomicU64;
+    use std::time::{Duration, Instant};
+
+    async fn run_line(options: SessionConfig, line: &str) -> String {
+        let mut session = TerminalSession::start(options)
+            .await
+            .expect("start session");
+        wait_for_prompt(&session.handle()).await;
+        session
+            .handle()
+            .send_input(format!("{line}\r"))
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let snapshot = session.snapshot().await.expect("snapshot");
+        session.shutdown().await.expect("shutdown session");
+        snapshot.render()
+    }
 
     #[test]
     fn render_replaces_cursor_and_adds_footer() {
@@ -416,6 +930,13 @@ mod tests {
             rows: 2,
             cursor: Some((1, 0)),
             lines: vec!["abc".to_string(), "xyz".to_string()],
+            command: None,
+            cwd: None,
+            title: None,
+            foreground: None,
+            modes: TerminalModes::default(),
+            bell_count: 0,
+            new_bells: 0,
         };
 
         assert_eq!(
@@ -431,6 +952,13 @@ mod tests {
             rows: 1,
             cursor: Some((4, 0)),
             lines: vec!["ab   ".to_string()],
+            command: None,
+            cwd: None,
+            title: None,
+            foreground: None,
+            modes: TerminalModes::default(),
+            bell_count: 0,
+            new_bells: 0,
         };
 
         assert_eq!(
@@ -446,6 +974,13 @@ mod tests {
             rows: 1,
             cursor: Some((0, 0)),
             lines: vec!["\"".to_string()],
+            command: None,
+            cwd: None,
+            title: None,
+            foreground: None,
+            modes: TerminalModes::default(),
+            bell_count: 0,
+            new_bells: 0,
         };
 
         assert_eq!(
@@ -461,6 +996,13 @@ mod tests {
             rows: 1,
             cursor: None,
             lines: vec!["abc".to_string()],
+            command: None,
+            cwd: None,
+            title: None,
+            foreground: None,
+            modes: TerminalModes::default(),
+            bell_count: 0,
+            new_bells: 0,
         };
 
         assert_eq!(
@@ -468,4 +1010,316 @@ mod tests {
             "abc\nCursor info: row=-, col=-, char=\"\""
         );
     }
+
+    #[test]
+    fn redraw_paints_text_and_cursor() {
+        let snapshot = TerminalSnapshot {
+            cols: 10,
+            rows: 2,
+            cursor: Some((2, 1)),
+            lines: vec!["$ ls   ".to_string(), "$ ".to_string()],
+            command: None,
+            cwd: None,
+            title: None,
+            foreground: None,
+            modes: TerminalModes::default(),
+            bell_count: 0,
+            new_bells: 0,
+        };
+
+        assert_eq!(
+            snapshot.redraw_sequence(),
+            "\x1b[0m\x1b[H\x1b[2J$ ls\r\n$\x1b[2;3H"
+        );
+    }
+
+    #[tokio::test]
+    async fn starts_shell_in_configured_cwd_with_allowlisted_env() {
+        let mut options = shell_session_config();
+        options.shell.cwd = Some("/usr".into());
+        options.shell.inherit_env = InheritEnv::Allowlist(vec!["PATH".to_string()]);
+
+        let screen = run_line(options, "echo d=$PWD h=${HOME:-none} p=${PATH:+set}").await;
+
+        assert!(screen.contains("d=/usr h=none p=set"), "{screen}");
+    }
+
+    #[tokio::test]
+    async fn unset_env_removes_inherited_variables() {
+        let mut options = shell_session_config();
+        options.shell.unset_env = vec!["HOME".to_string()];
+
+        let screen = run_line(options, "echo h=${HOME:-none} t=$TERM").await;
+
+        assert!(screen.contains("h=none t=xterm-256color"), "{screen}");
+    }
+
+    #[tokio::test]
+    async fn answers_cursor_and_color_queries() {
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+        // Echo goes off first: the reply can arrive before `read -s` would turn it off.
+        let line = r#"stty -echo; printf '\e[6n'; read -r -d R pos; printf '\e]11;?\a'; read -r -d $'\a' bg; stty echo; echo "pos=${pos#*[} bg=${bg#*;}""#;
+        handle
+            .send_input(format!("{line}\r"))
+            .await
+            .expect("send input");
+        // Two round trips through the worker, which a fixed wait can miss under load.
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut screen = String::new();
+        while Instant::now() < deadline && !screen.contains("bg=rgb") {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            screen = handle.snapshot().await.expect("snapshot").render();
+        }
+        session.shutdown().await.expect("shutdown session");
+
+        assert!(screen.contains("pos=5;1 bg=rgb:0000/0000/0000"), "{screen}");
+    }
+
+    #[tokio::test]
+    async fn footer_reports_running_commands_and_exit_status() {
+        let mut options = shell_session_config();
+        options.shell.integration = true;
+        let mut session = TerminalSession::start(options)
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+
+        handle
+            .send_input("sleep 1; false\r")
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let running = handle.snapshot().await.expect("snapshot").render();
+        tokio::time::sleep(Duration::from_millis(1200)).await;
+        let finished = handle.snapshot().await.expect("snapshot").render();
+        session.shutdown().await.expect("shutdown session");
+
+        assert!(running.contains("\nCommand: running for "), "{running}");
+        assert!(
+            finished.contains("\nCommand: idle; the last one exited with status 1 after 1."),
+            "{finished}"
+        );
+        assert!(finished.starts_with("$ sleep 1; false\n$ "), "{finished}");
+    }
+
+    #[tokio::test]
+    async fn signals_the_foreground_job_but_not_the_shell() {
+        let mut options = shell_session_config();
+        options.shell.integration = true;
+        let mut session = TerminalSession::start(options)
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+
+        let at_prompt = handle.signal_foreground(JobSignal::Int).await;
+        // With signal keys off, Ctrl-C would only type a byte.
+        handle
+            .send_input("stty -isig; sleep 30\r")
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        handle
+            .signal_foreground(JobSignal::Int)
+            .await
+            .expect("signal sleep");
+        let deadline = Instant::now() + Duration::from_secs(5);
+        let mut status = None;
+        while Instant::now() < deadline {
+            status = handle.snapshot().await.expect("snapshot").command;
+            if status
+                .as_ref()
+                .is_some_and(|status| status.running_for.is_none())
+            {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        session.shutdown().await.expect("shutdown session");
+
+        let err = at_prompt.expect_err("the shell is not a job");
+        assert!(err.to_string().contains("waiting at its prompt"), "{err:#}");
+        assert_eq!(status.expect("command status").last_exit, Some(130));
+    }
+
+    #[tokio::test]
+    async fn lists_the_processes_under_the_shell() {
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+
+        handle
+            .send_input("sleep 30 | cat\r")
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let tree = handle.processes().await.expect("process tree");
+        session.shutdown().await.expect("shutdown session");
+
+        let commands: Vec<(usize, &str, bool)> = tree
+            .processes
+            .iter()
+            .map(|process| (process.depth, process.command.as_str(), process.foreground))
+            .collect();
+        assert_eq!(
+            commands,
+            [
+                (0, "/bin/bash --noprofile --norc", false),
+                (1, "sleep 30", true),
+                (1, "cat", true),
+            ]
+        );
+        assert!(tree.render().contains(" S+ "), "{}", tree.render());
+    }
+
+    #[tokio::test]
+    async fn footer_names_the_foreground_process() {
+        let mut options = shell_session_config();
+        options.shell.report_foreground = true;
+        let mut session = TerminalSession::start(options)
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+
+        handle.send_input("cat\r").await.expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let snapshot = handle.snapshot().await.expect("snapshot");
+        session.shutdown().await.expect("shutdown session");
+
+        let foreground = snapshot.foreground.as_ref().expect("foreground process");
+        assert_eq!(foreground.name, "cat");
+        assert_eq!(foreground.activity, ForegroundActivity::ReadingInput);
+        assert!(snapshot.render().contains(&format!(
+            "Foreground: cat (pid {}), waiting for input\n",
+            foreground.pid
+        )));
+    }
+
+    #[tokio::test]
+    async fn footer_reports_modes_and_new_bells() {
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+
+        handle
+            .send_input("printf '\\a\\a\\e[?1049h\\e[?1h'; sleep 5\r")
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let seen = AtomicU64::new(0);
+        let mut first = handle.snapshot().await.expect("snapshot");
+        first.count_new_bells(&seen);
+        let mut second = handle.snapshot().await.expect("snapshot");
+        second.count_new_bells(&seen);
+        session.shutdown().await.expect("shutdown session");
+
+        assert_eq!(first.bell_count, 2);
+        assert!(first.modes.alternate_screen);
+        assert!(first.modes.application_cursor);
+        assert!(!first.modes.bracketed_paste);
+        assert!(
+            first.render().contains(
+                "\nTerminal: alternate screen, application cursor keys, the bell rang 2 times since the last snapshot\n"
+            ),
+            "{}",
+            first.render()
+        );
+        assert!(
+            second
+                .render()
+                .contains("\nTerminal: alternate screen, application cursor keys\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn footer_reports_working_directory_and_title() {
+        let mut options = shell_session_config();
+        options.shell.integration = true;
+        let mut session = TerminalSession::start(options)
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+
+        handle
+            .send_input("cd /usr; printf '\\e]2;hello\\a'\r")
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let snapshot = handle.snapshot().await.expect("snapshot");
+        session.shutdown().await.expect("shutdown session");
+
+        assert_eq!(snapshot.cwd.as_deref(), Some("/usr"));
+        assert_eq!(snapshot.title.as_deref(), Some("hello"));
+        let rendered = snapshot.render();
+        assert!(
+            rendered.contains("\nTitle: hello\nWorking directory: /usr\nCommand: idle"),
+            "{rendered}"
+        );
+    }
+
+    #[tokio::test]
+    async fn streams_output_to_subscribers_and_resizes() {
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+
+        let (_, mut output) = handle.subscribe_output().await.expect("subscribe");
+        handle.resize(40, 10).await.expect("resize");
+        handle.send_input("stty size\r").await.expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        let snapshot = session.snapshot().await.expect("snapshot");
+        session.shutdown().await.expect("shutdown session");
+
+        let mut streamed = Vec::new();
+        while let Ok(chunk) = output.try_recv() {
+            streamed.extend(chunk);
+        }
+        assert!(String::from_utf8_lossy(&streamed).contains("10 40"));
+        assert_eq!((snapshot.cols, snapshot.rows), (40, 10));
+        assert!(snapshot.render().contains("10 40"));
+    }
+
+    #[tokio::test]
+    async fn leaves_queries_to_an_attached_terminal() {
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+        let line =
+            r#"stty -echo; printf '\e[6n'; read -r -t 1 -d R pos; stty echo; echo "got=${#pos}""#;
+
+        let (_, output) = handle.attach_output().await.expect("attach");
+        handle
+            .send_input(format!("{line}\r"))
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        let attached = handle.snapshot().await.expect("snapshot").render();
+        drop(output);
+        handle
+            .send_input(format!("clear; {line}\r"))
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        let detached = handle.snapshot().await.expect("snapshot").render();
+        session.shutdown().await.expect("shutdown session");
+
+        assert!(attached.contains("got=0"), "{attached}");
+        assert!(!detached.contains("got=0"), "{detached}");
+        assert!(detached.contains("got="), "{detached}");
+    }
 }
@@ -0,0 +1,267 @@
+use serde_json::{Value, json};
+use std::collections::{BTreeMap, VecDeque};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::config::{
+    InheritEnv, LandlockConfig, LimitsConfig, LlmConfig, RedactionConfig, SandboxConfig,
+    ServeConfig, SessionConfig, ShellConfig, SkinMode, WatchConfig,
+};
+use crate::terminal_session::TerminalSessionHandle;
+
+pub const TEST_API_KEY: &str = "test-key";
+
+/// A small bash session with a fixed prompt so snapshots are deterministic.
+pub fn shell_session_config() -> SessionConfig {
+    let mut env = BTreeMap::new();
+    for (key, value) in [
+        ("COLUMNS", "40"),
+        ("LINES", "6"),
+        ("TERM", "xterm-256color"),
+        ("PS1", "$ "),
+        ("PROMPT_COMMAND", ""),
+        ("HISTFILE", "/dev/null"),
+    ] {
+        env.insert(key.to_string(), value.to_string());
+    }
+    SessionConfig {
+        wait_ms: 500,
+        yolo: true,
+        shell: ShellConfig {
+            program: "/bin/bash".to_string(),
+            args: vec!["--noprofile".to_string(), "--norc".to_string()],
+            env,
+            cwd: None,
+            inherit_env: InheritEnv::default(),
+            unset_env: Vec::new(),
+            login: false,
+            interactive: false,
+            // Timings in the command status line would make snapshots unreproducible.
+            integration: false,
+            // Neither would the foreground process's pid.
+            report_foreground: false,
+        },
+        llm: LlmConfig {
+            api_key: TEST_API_KEY.to_string(),
+            base_url: None,
+            skin: SkinMode::Default,
+            initial_prompt: "Use the raw_input tool.".to_string(),
+        },
+        redaction: RedactionConfig::default(),
+        sandbox: SandboxConfig::default(),
+        landlock: LandlockConfig::default(),
+        limits: LimitsConfig::default(),
+        serve: ServeConfig::default(),
+        watch: WatchConfig::default(),
+    }
+}
+
+/// Waits for the shell to draw its first prompt, so early input is not echoed
+/// by the line discipline before bash has taken over the terminal.
+pub async fn wait_for_prompt(session: &TerminalSessionHandle) {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    while Instant::now() < deadline {
+        let snapshot = session.snapshot().await.expect("snapshot session");
+        if snapshot.lines.iter().any(|line| !line.trim().is_empty()) {
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+    panic!("shell did not draw a prompt within 5 seconds");
+}
+
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub path: String,
+    pub headers: BTreeMap<String, String>,
+    pub body: Value,
+}
+
+struct CannedResponse {
+    status: u16,
+    body: Value,
+}
+
+/// Local stand-in for the OpenAI Responses API that serves queued replies in order.
+pub struct FakeLlmServer {
+    base_url: String,
+    responses: Arc<Mutex<VecDeque<CannedResponse>>>,
+    requests: Arc<Mutex<Vec<RecordedRequest>>>,
+    tool_calls: AtomicUsize,
+}
+
+impl FakeLlmServer {
+    pub fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind fake LLM server");
+        let base_url = format!(
+            "http://{}/v1",
+            listener.local_addr().expect("fake LLM server address")
+        );
+        let responses = Arc::new(Mutex::new(VecDeque::new()));
+        let requests = Arc::new(Mutex::new(Vec::new()));
+
+        let thread_responses = responses.clone();
+        let thread_requests = requests.clone();
+        thread::Builder::new()
+            .name("fake-llm-server".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    let Ok(stream) = stream else {
+                        continue;
+                    };
+                    let _ = serve_connection(stream, &thread_responses, &thread_requests);
+                }
+            })
+            .expect("spawn fake LLM server thread");
+
+        Self {
+            base_url,
+            responses,
+            requests,
+            tool_calls: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    pub fn push_tool_call(&self, name: &str, arguments: Value) {
+        let call_number = self.tool_calls.fetch_add(1, Ordering::SeqCst) + 1;
+        self.push(
+            200,
+            response_body(json!({
+                "type": "function_call",
+                "id": format!("fc_{call_number}"),
+                "call_id": format!("call_{call_number}"),
+                "name": name,
+                "arguments": arguments.to_string(),
+                "status": "completed"
+            })),
+        );
+    }
+
+    pub fn push_text(&self, text: &str) {
+        self.push(
+            200,
+            response_body(json!({
+                "type": "message",
+                "id": "msg_1",
+                "role": "assistant",
+                "status": "completed",
+                "content": [{ "type": "output_text", "text": text }]
+            })),
+        );
+    }
+
+    pub fn push_error(&self, status: u16, message: &str) {
+        self.push(
+            status,
+            json!({ "error": { "message": message, "type": "server_error" } }),
+        );
+    }
+
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.requests
+            .lock()
+            .expect("fake LLM mutex should not be poisoned")
+            .clone()
+    }
+
+    fn push(&self, status: u16, body: Value) {
+        self.responses
+            .lock()
+            .expect("fake LLM mutex should not be poisoned")
+            .push_back(CannedResponse { status, body });
+    }
+}
+
+fn response_body(output: Value) -> Value {
+    json!({
+        "id": "resp_1",
+        "object": "response",
+        "created_at": 0,
+        "status": "completed",
+        "error": null,
+        "incomplete_details": null,
+        "instructions": null,
+        "max_output_tokens": null,
+        "model": "fake-model",
+        "usage": {
+            "input_tokens": 10,
+            "output_tokens": 5,
+            "output_tokens_details": { "reasoning_tokens": 0 },
+            "total_tokens": 15
+        },
+        "output": [output],
+        "tools": []
+    })
+}
+
+fn serve_connection(
+    stream: TcpStream,
+    responses: &Mutex<VecDeque<CannedResponse>>,
+    requests: &Mutex<Vec<RecordedRequest>>,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = stream;
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+    let path = request_line
+        .split_whitespace()
+        .nth(1)
+        .unwrap_or_default()
+        .to_string();
+
+    let mut headers = BTreeMap::new();
+    loop {
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+        }
+    }
+
+    let content_length = headers
+        .get("content-length")
+        .and_then(|value| value.parse::<usize>().ok())
+        .unwrap_or(0);
+    let mut body = vec![0_u8; content_length];
+    reader.read_exact(&mut body)?;
+
+    requests
+        .lock()
+        .expect("fake LLM mutex should not be poisoned")
+        .push(RecordedRequest {
+            path,
+            headers,
+            body: serde_json::from_slice(&body).unwrap_or(Value::Null),
+        });
+
+    let canned = responses
+        .lock()
+        .expect("fake LLM mutex should not be poisoned")
+        .pop_front()
+        .unwrap_or_else(|| CannedResponse {
+            status: 500,
+            body: json!({ "error": { "message": "fake LLM server has no canned response" } }),
+        });
+    let payload = canned.body.to_string();
+    write!(
+        writer,
+        "HTTP/1.1 {} Canned\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+        canned.status,
+        payload.len(),
+        payload
+    )?;
+    writer.flush()
+}
@@ -0,0 +1,192 @@
+use anyhow::{Context, Result, bail};
+use std::fs;
+use std::io::Write;
+use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{UnixListener, UnixStream};
+use tokio::task::JoinHandle;
+use tracing::{debug, warn};
+
+use crate::config::default_state_dir;
+use crate::terminal_session::TerminalSessionHandle;
+
+const SOCKET_EXTENSION: &str = "sock";
+
+/// Directory holding one socket per running session, named after its pid.
+pub fn socket_dir() -> Result<PathBuf> {
+    match std::env::var_os("XDG_RUNTIME_DIR") {
+        Some(runtime) => Ok(PathBuf::from(runtime).join("gibberish")),
+        None => Ok(default_state_dir()?.join("run")),
+    }
+}
+
+/// Serves a read-only stream of the session's PTY output to `gibberish watch`.
+/// The socket is removed when the server is dropped.
+pub struct WatchServer {
+    path: PathBuf,
+    accept: JoinHandle<()>,
+}
+
+impl WatchServer {
+    pub fn start(session: TerminalSessionHandle) -> Result<Self> {
+        let dir = socket_dir()?;
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(&dir)
+            .with_context(|| {
+                format!("failed to create watch socket directory {}", dir.display())
+            })?;
+        let path = dir.join(format!("{}.{SOCKET_EXTENSION}", std::process::id()));
+        // A leftover socket with our pid belongs to a dead process.
+        let _ = fs::remove_file(&path);
+        let listener = UnixListener::bind(&path)
+            .with_context(|| format!("failed to bind watch socket {}", path.display()))?;
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("failed to restrict watch socket {}", path.display()))?;
+
+        let accept = tokio::spawn(async move {
+            loop {
+                match listener.accept().await {
+                    Ok((stream, _)) => {
+                        let session = session.clone();
+                        tokio::spawn(async move {
+                            if let Err(err) = stream_to_watcher(&session, stream).await {
+                                debug!("watcher disconnected: {err:#}");
+                            }
+                        });
+                    }
+                    Err(err) => {
+                        warn!("watch socket stopped accepting: {err}");
+                        return;
+                    }
+                }
+            }
+        });
+        Ok(Self { path, accept })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for WatchServer {
+    fn drop(&mut self) {
+        self.accept.abort();
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+async fn stream_to_watcher(session: &TerminalSessionHandle, mut stream: UnixStream) -> Result<()> {
+    let (snapshot, mut output) = session.subscribe_output().await?;
+    stream
+        .write_all(snapshot.redraw_sequence().as_bytes())
+        .await?;
+    while let Some(bytes) = output.recv().await {
+        stream.write_all(&bytes).await?;
+    }
+    Ok(())
+}
+
+/// Copies a session's terminal to stdout until the session ends.
+/// `target` is a pid or socket path; without one the newest live session is used.
+pub async fn watch(target: Option<&str>) -> Result<()> {
+    let mut stream = match target {
+        Some(target) => {
+            let path = match target.parse::<u32>() {
+                Ok(pid) => socket_dir()?.join(format!("{pid}.{SOCKET_EXTENSION}")),
+                Err(_) => PathBuf::from(target),
+            };
+            UnixStream::connect(&path)
+                .await
+                .with_context(|| format!("failed to connect to {}", path.display()))?
+        }
+        None => connect_newest(&socket_dir()?).await?,
+    };
+
+    let mut stdout = std::io::stdout();
+    let mut buf = [0_u8; 8192];
+    loop {
+        let n = stream
+            .read(&mut buf)
+            .await
+            .context("failed to read from session")?;
+        if n == 0 {
+            break;
+        }
+        stdout
+            .write_all(&buf[..n])
+            .and_then(|()| stdout.flush())
+            .context("failed to write to stdout")?;
+    }
+    eprintln!("\r\nsession ended");
+    Ok(())
+}
+
+async fn connect_newest(dir: &Path) -> Result<UnixStream> {
+    let mut sockets: Vec<(std::time::SystemTime, PathBuf)> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == SOCKET_EXTENSION))
+        .filter_map(|path| Some((fs::metadata(&path).ok()?.modified().ok()?, path)))
+        .collect();
+    sockets.sort_by_key(|(modified, _)| std::cmp::Reverse(*modified));
+
+    // Sessions that crashed leave their socket behind, so fall through to older ones.
+    for (_, path) in sockets {
+        if let Ok(stream) = UnixStream::connect(&path).await {
+            return Ok(stream);
+        }
+    }
+    bail!("no running gibberish session found in {}", dir.display())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::stream_to_watcher;
+    use crate::terminal_session::TerminalSession;
+    use crate::test_support::{shell_session_config, wait_for_prompt};
+    use std::time::Duration;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::UnixStream;
+
+    #[tokio::test]
+    async fn watcher_gets_screen_then_live_output() {
+        let mut session = TerminalSession::start(shell_session_config())
+            .await
+            .expect("start session");
+        let handle = session.handle();
+        wait_for_prompt(&handle).await;
+        handle
+            .send_input("echo before\r")
+            .await
+            .expect("send input");
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let (server, mut client) = UnixStream::pair().expect("socket pair");
+        let streamer = tokio::spawn({
+            let handle = handle.clone();
+            async move { stream_to_watcher(&handle, server).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        handle.send_input("echo after\r").await.expect("send input");
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        session.shutdown().await.expect("shutdown session");
+
+        let mut received = Vec::new();
+        client
+            .read_to_end(&mut received)
+            .await
+            .expect("read stream");
+        streamer.await.expect("join streamer").expect("stream");
+        let received = String::from_utf8_lossy(&received);
+        let before = received.find("before").expect("initial screen");
+        let after = received.rfind("after").expect("live output");
+        assert!(received.starts_with("\x1b[0m\x1b[H\x1b[2J"));
+        assert!(before < after);
+    }
+}
@@ -0,0 +1,56 @@
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+const CONFIG: &str = r#"
+wait_ms = 100
+
+[shell]
+program = "/bin/bash"
+args = ["--noprofile", "--norc"]
+inherit_env = ["PATH"]
+
+[shell.env]
+COLUMNS = "80"
+LINES = "24"
+
+[llm]
+initial_prompt = "unused"
+"#;
+
+#[test]
+fn starts_without_llm_settings() {
+    let config = std::env::temp_dir().join(format!("gibberish-mcp-{}.toml", std::process::id()));
+    std::fs::write(&config, CONFIG).expect("write config");
+    let mut child = Command::new(env!("CARGO_BIN_EXE_gibberish"))
+        .arg("--config")
+        .arg(&config)
+        .arg("mcp")
+        .env_remove("OPENAI_API_KEY")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .expect("run gibberish mcp");
+    child
+        .stdin
+        .take()
+        .expect("stdin")
+        .write_all(
+            br#"{"jsonrpc":"2.0","id":1,"method":"initialize","params":{"protocolVersion":"2025-06-18","capabilities":{},"clientInfo":{"name":"test","version":"0"}}}
+"#,
+        )
+        .expect("write initialize");
+    let output = child.wait_with_output().expect("wait for gibberish mcp");
+    std::fs::remove_file(&config).expect("remove config");
+
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let stdout = String::from_utf8(output.stdout).expect("utf-8 stdout");
+    assert!(
+        stdout.contains(r#""protocolVersion":"2025-06-18""#),
+        "{stdout}"
+    );
+}
@@ -0,0 +1,71 @@
+use std::path::Path;
+use std::process::{Command, Output};
+
+const NAMESPACE_ERROR: &str = "failed to create sandbox namespaces";
+
+fn run_sandboxed(workdir: &Path, network: bool, script: &str) -> Option<Output> {
+    let plan = serde_json::json!({
+        "workdir": workdir,
+        "read_only": ["/usr", "/bin", "/sbin", "/lib", "/lib64", "/etc", "/does-not-exist"],
+        "network": network,
+    });
+    let output = Command::new(env!("CARGO_BIN_EXE_gibberish"))
+        .args(["--sandbox-init", "--", "/bin/sh", "-c", script])
+        .env("GIBBERISH_SANDBOX_PLAN", plan.to_string())
+        .output()
+        .expect("run sandbox init");
+    if String::from_utf8_lossy(&output.stderr).contains(NAMESPACE_ERROR) {
+        // CI runners and containers often disable unprivileged user namespaces.
+        eprintln!("skipping: user namespaces are unavailable here");
+        return None;
+    }
+    Some(output)
+}
+
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("gibberish-sandbox-{name}-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).expect("create scratch dir");
+    dir.canonicalize().expect("canonicalize scratch dir")
+}
+
+#[test]
+fn workdir_is_writable_and_system_paths_are_read_only() {
+    let workdir = scratch_dir("fs");
+    let Some(output) = run_sandboxed(
+        &workdir,
+        true,
+        "pwd; echo $$; touch inside && echo wrote; touch /usr/gibberish-probe 2>/dev/null || echo ro",
+    ) else {
+        return;
+    };
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(
+        lines,
+        [workdir.to_str().expect("utf-8 path"), "1", "wrote", "ro"]
+    );
+    assert!(workdir.join("inside").exists());
+    std::fs::remove_dir_all(&workdir).expect("remove scratch dir");
+}
+
+#[test]
+fn propagates_exit_status_and_hides_host_tmp() {
+    let workdir = scratch_dir("exit");
+    let marker = std::env::temp_dir().join(format!("gibberish-host-marker-{}", std::process::id()));
+    std::fs::write(&marker, "host").expect("write marker");
+    let script = format!("test -e {} && exit 9; exit 3", marker.display());
+    let Some(output) = run_sandboxed(&workdir, false, &script) else {
+        std::fs::remove_file(&marker).expect("remove marker");
+        return;
+    };
+
+    std::fs::remove_file(&marker).expect("remove marker");
+    std::fs::remove_dir_all(&workdir).expect("remove scratch dir");
+    assert_eq!(output.status.code(), Some(3));
+}
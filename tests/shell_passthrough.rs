@@ -0,0 +1,107 @@
+use std::os::unix::process::CommandExt;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+const CONFIG: &str = r#"
+wait_ms = 100
+
+[shell]
+program = "/bin/bash"
+args = ["--noprofile", "--norc"]
+inherit_env = ["PATH"]
+
+[shell.env]
+COLUMNS = "80"
+LINES = "24"
+GIBBERISH_TEST = "from-config"
+
+[llm]
+initial_prompt = "unused"
+"#;
+
+fn write_config(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!(
+        "gibberish-passthrough-{name}-{}.toml",
+        std::process::id()
+    ));
+    std::fs::write(&path, CONFIG).expect("write config");
+    path
+}
+
+fn run(command: &mut Command) -> String {
+    let output = command
+        .env_remove("OPENAI_API_KEY")
+        .stdin(Stdio::null())
+        .output()
+        .expect("run gibberish");
+    assert!(
+        output.status.success(),
+        "stderr: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).expect("utf-8 stdout")
+}
+
+#[test]
+fn non_interactive_command_runs_in_configured_shell() {
+    let config = write_config("plain");
+    let stdout = run(Command::new(env!("CARGO_BIN_EXE_gibberish"))
+        .arg("--config")
+        .arg(&config)
+        .arg("--shell-compat")
+        .args([
+            "-c",
+            "echo $GIBBERISH_TEST; shopt -q login_shell || echo not-login",
+        ]));
+    std::fs::remove_file(&config).expect("remove config");
+
+    assert_eq!(stdout, "from-config\nnot-login\n");
+}
+
+#[test]
+fn dash_argv0_starts_a_login_shell() {
+    let config = write_config("login");
+    let stdout = run(Command::new(env!("CARGO_BIN_EXE_gibberish"))
+        .arg0("-gibberish")
+        .arg("--config")
+        .arg(&config)
+        .args(["-c", "echo $0; shopt -q login_shell && echo login"]));
+    std::fs::remove_file(&config).expect("remove config");
+
+    assert_eq!(stdout, "-bash\nlogin\n");
+}
+
+#[test]
+fn shell_env_naming_gibberish_runs_commands_in_the_shell() {
+    let config = write_config("shell-env");
+    let stdout = run(Command::new(env!("CARGO_BIN_EXE_gibberish"))
+        .env("SHELL", env!("CARGO_BIN_EXE_gibberish"))
+        .arg("--config")
+        .arg(&config)
+        .args(["-c", "echo $GIBBERISH_TEST"]));
+    std::fs::remove_file(&config).expect("remove config");
+
+    assert_eq!(stdout, "from-config\n");
+}
+
+#[test]
+fn command_without_a_shell_signal_goes_to_the_agent() {
+    let config = write_config("agent");
+    let output = Command::new(env!("CARGO_BIN_EXE_gibberish"))
+        .env_remove("OPENAI_API_KEY")
+        .env_remove("RUST_LOG")
+        .env_remove("SHELL")
+        .arg("--config")
+        .arg(&config)
+        .args(["-c", "echo $GIBBERISH_TEST"])
+        .stdin(Stdio::null())
+        .output()
+        .expect("run gibberish");
+    std::fs::remove_file(&config).expect("remove config");
+
+    // The config has no API key, so the agent can't start; the shell would have.
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--shell-compat"), "stderr: {stderr}");
+}